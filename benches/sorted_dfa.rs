@@ -0,0 +1,39 @@
+#![feature(test)]
+
+extern crate automata;
+extern crate test;
+
+use automata::DFA;
+use automata::Transition::Input;
+use std::collections::{HashMap, HashSet};
+use test::Bencher;
+
+fn sparse_dfa() -> DFA<usize, u32> {
+    let mut transitions = HashMap::new();
+    for state in 0..16 {
+        for c in 0..512 {
+            transitions.insert((state, Input(c * 7919)), (state + c as usize) % 16);
+        }
+    }
+    let mut accept_states = HashSet::new();
+    accept_states.insert(0);
+    DFA::new(0, accept_states, transitions)
+}
+
+fn input() -> Vec<u32> {
+    (0..1024).map(|i| (i * 31 % 512) * 7919).collect()
+}
+
+#[bench]
+fn bench_hash_lookup(b: &mut Bencher) {
+    let dfa = sparse_dfa();
+    let input = input();
+    b.iter(|| dfa.accepts(&input));
+}
+
+#[bench]
+fn bench_sorted_lookup(b: &mut Bencher) {
+    let sorted = sparse_dfa().compile_sorted();
+    let input = input();
+    b.iter(|| sorted.accepts(&input));
+}
@@ -1,12 +1,15 @@
-use Automaton;
+use {Automaton, AutomatonError};
 use std::fmt::Display;
 use std::io::Write;
 use std::fs::OpenOptions;
-use std::collections::{HashSet, HashMap};
-use std::hash::Hash;
+use std::collections::{HashSet, HashMap, VecDeque, BTreeSet};
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::ops::RangeInclusive;
+use std::cmp;
 
-use nfa::Transition;
-use nfa::Transition::{Input, Epsilon, Anything};
+use nfa::{Transition, Symbol, alphabet_intervals};
+use nfa::Transition::{Input, Epsilon, Anything, Range};
 
 #[derive(Debug, Clone)]
 pub struct DFA<S: Eq + PartialEq + Hash = usize, I: Eq + PartialEq + Hash = char> {
@@ -15,109 +18,1421 @@ pub struct DFA<S: Eq + PartialEq + Hash = usize, I: Eq + PartialEq + Hash = char
     pub transitions: HashMap<(S, Transition<I>), S>
 }
 
+pub struct DFAExecutor<'a, S: 'a + Eq + Hash, I: 'a + Eq + Hash> {
+    dfa: &'a DFA<S, I>,
+    stepper: Stepper<'a, S, I>,
+    cur_state: Option<&'a S>
+}
+
+pub struct IncrementalValidator<'a, S: 'a + Eq + Hash, I: 'a + Eq + Hash> {
+    dfa: &'a DFA<S, I>,
+    stepper: Stepper<'a, S, I>,
+    live: HashSet<&'a S>,
+    cur_state: Option<&'a S>
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum PushResult {
+    // The input so far is accepted
+    Accepted,
+    // The input so far isn't accepted, but some extension of it is
+    Viable,
+    // No extension of the input so far can be accepted
+    Dead
+}
+
 pub struct DFAIter<'a, S: 'a, I: 'a> {
     input: Vec<I>,
-    transitions: &'a HashMap<(S, Transition<I>), S>,
+    stepper: Stepper<'a, S, I>,
     pos: usize,
+    done: bool,
     cur_state: &'a S
 }
 
-impl<S: Eq + Hash, I: Eq + Hash> DFA<S, I> {
-    pub fn new(start: S, accept_states: HashSet<S>, transitions: HashMap<(S, Transition<I>), S>) -> DFA<S, I> {
-        DFA { start: start, accept_states: accept_states, transitions: transitions }
+impl<S: Eq + Hash, I: Eq + Hash> DFA<S, I> {
+    pub fn new(start: S, accept_states: HashSet<S>, transitions: HashMap<(S, Transition<I>), S>) -> DFA<S, I> {
+        DFA { start: start, accept_states: accept_states, transitions: transitions }
+    }
+
+    pub fn get_accept_states(&self) -> &HashSet<S> {
+        &self.accept_states
+    }
+
+    pub fn get_start_state(&self) -> &S {
+        &self.start
+    }
+
+    pub fn get_transitions(&self) -> &HashMap<(S, Transition<I>), S> {
+        &self.transitions
+    }
+
+    // Structural equality: same start, accept states and transitions with the same state labels.
+    // Two machines can recognize the same language without having the same structure; use
+    // `equivalent` to compare languages.
+    pub fn same_structure(&self, other: &DFA<S, I>) -> bool {
+        self.start == other.start && self.accept_states == other.accept_states &&
+            self.transitions == other.transitions
+    }
+
+    pub fn is_explicit(&self) -> bool {
+        self.transitions.keys().all(|&(_, ref trans)| match *trans { Input(_) => true, _ => false })
+    }
+
+    pub fn accept_states_sorted(&self) -> Vec<S> where S: Ord + Clone {
+        let mut accept_states: Vec<S> = self.accept_states.iter().cloned().collect();
+        accept_states.sort();
+        accept_states
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.states().len()
+    }
+
+    fn stepper(&self) -> Stepper<S, I> {
+        Stepper::new(&self.transitions)
+    }
+
+    fn states(&self) -> HashSet<&S> {
+        let mut states = HashSet::new();
+        states.insert(&self.start);
+        states.extend(self.accept_states.iter());
+        for (&(ref from, _), to) in self.transitions.iter() {
+            states.insert(from);
+            states.insert(to);
+        }
+        states
+    }
+}
+
+impl<S: Eq + Hash + Clone> DFA<S, char> {
+    pub fn coalesce_ranges(&self) -> DFA<S, char> {
+        let mut transitions = HashMap::new();
+        let mut inputs = HashMap::new();
+        for (&(ref from, ref trans), to) in self.transitions.iter() {
+            match *trans {
+                Input(c) => inputs.entry(from.clone()).or_insert(Vec::new()).push((c, to.clone())),
+                _ => { transitions.insert((from.clone(), trans.clone()), to.clone()); }
+            }
+        }
+
+        for (from, mut edges) in inputs.into_iter() {
+            edges.sort_by(|a, b| a.0.cmp(&b.0));
+
+            let mut i = 0;
+            while i < edges.len() {
+                let (lo, ref to) = edges[i];
+                let mut hi = lo;
+                let mut j = i + 1;
+                while j < edges.len() && edges[j].1 == *to &&
+                      Some(edges[j].0) == ::std::char::from_u32(hi as u32 + 1) {
+                    hi = edges[j].0;
+                    j += 1;
+                }
+
+                let trans = if lo == hi { Input(lo) } else { Range(lo, hi) };
+                transitions.insert((from.clone(), trans), to.clone());
+                i = j;
+            }
+        }
+
+        DFA::new(self.start.clone(), self.accept_states.clone(), transitions)
+    }
+}
+
+impl DFA<usize, char> {
+    // Builds the minimal DFA accepting the inputs whose longest listed prefix is marked `true`, so a
+    // longer rule refines a shorter one. Inputs that don't start with any listed prefix are rejected.
+    pub fn from_prefix_rules(rules: &[(&str, bool)]) -> Result<DFA<usize, char>, AutomatonError> {
+        const ACCEPT: usize = 0;
+        const ROOT: usize = 1;
+
+        // A trie of the prefixes, with the verdict of each rule at the node it ends on
+        let mut parents = vec![ACCEPT, ROOT];
+        let mut verdicts = HashMap::new();
+        let mut transitions = HashMap::new();
+        for &(prefix, accept) in rules {
+            let mut cur_state = ROOT;
+            for c in prefix.chars() {
+                cur_state = match transitions.get(&(cur_state, Input(c))) {
+                    Some(&s) => s,
+                    None => {
+                        let s = parents.len();
+                        parents.push(cur_state);
+                        transitions.insert((cur_state, Input(c)), s);
+                        s
+                    }
+                };
+            }
+            if verdicts.insert(cur_state, accept).is_some() {
+                return Err(AutomatonError::DuplicatePrefix(prefix.to_string()))
+            }
+        }
+
+        // Parents are created before their children, so each node can inherit its parent's verdict
+        let mut accept_states = HashSet::new();
+        accept_states.insert(ACCEPT);
+        let mut inherited = vec![None; parents.len()];
+        for s in ROOT..parents.len() {
+            inherited[s] = verdicts.get(&s).cloned().or(inherited[parents[s]]);
+            if inherited[s] == Some(true) {
+                accept_states.insert(s);
+                transitions.insert((s, Anything), ACCEPT);
+            }
+        }
+        transitions.insert((ACCEPT, Anything), ACCEPT);
+
+        Ok(DFA::new(ROOT, accept_states, transitions).canonical_minimal())
+    }
+}
+
+// Steps through the transitions of a machine. The `Range` edges are indexed by source state when
+// the stepper is made, so a run makes one up front rather than scanning every edge per symbol.
+struct Stepper<'a, S: 'a, I: 'a> {
+    transitions: &'a HashMap<(S, Transition<I>), S>,
+    ranges: HashMap<&'a S, Vec<(&'a I, &'a I, &'a S)>>
+}
+
+impl<'a, S: Eq + Hash, I: Eq + Hash> Stepper<'a, S, I> {
+    fn new(transitions: &'a HashMap<(S, Transition<I>), S>) -> Stepper<'a, S, I> {
+        let mut ranges = HashMap::new();
+        for (&(ref from, ref trans), to) in transitions.iter() {
+            if let Range(ref lo, ref hi) = *trans {
+                ranges.entry(from).or_insert(Vec::new()).push((lo, hi, to));
+            }
+        }
+        Stepper { transitions: transitions, ranges: ranges }
+    }
+}
+
+impl<'a, S: Eq + Hash + Clone, I: Eq + Hash + Clone + PartialOrd> Stepper<'a, S, I> {
+    fn range(&self, state: &S, c: &I) -> Option<&(&'a I, &'a I, &'a S)> {
+        self.ranges.get(state).and_then(|edges| edges.iter().find(|r| *r.0 <= *c && *c <= *r.1))
+    }
+
+    fn step(&self, state: &S, c: &I) -> Option<&'a S> {
+        match self.transitions.get(&(state.clone(), Input(c.clone()))) {
+            Some(s) => Some(s),
+            None => match self.range(state, c) {
+                Some(r) => Some(r.2),
+                None => self.transitions.get(&(state.clone(), Anything))
+            }
+        }
+    }
+
+    fn step_edge(&self, state: &S, c: &I) -> Option<(Transition<I>, &'a S)> {
+        if let Some(s) = self.transitions.get(&(state.clone(), Input(c.clone()))) {
+            return Some((Input(c.clone()), s))
+        }
+        if let Some(r) = self.range(state, c) {
+            return Some((Range(r.0.clone(), r.1.clone()), r.2))
+        }
+        self.transitions.get(&(state.clone(), Anything)).map(|s| (Anything, s))
+    }
+
+    // Where `state` goes on the symbols that `label` stands for when machines are compared label by
+    // label: an `Input` on its own symbol, a `Range` on those of its symbols without an `Input`
+    // edge, and `Anything` on the symbols that no other label mentions. This is exact as long as
+    // ranges don't partially overlap each other; otherwise split them with `alphabet_intervals`.
+    fn probe(&self, state: &S, label: &Transition<I>) -> Option<&'a S> {
+        match *label {
+            Input(ref c) => self.step(state, c),
+            Range(ref lo, ref hi) => match self.transitions.get(&(state.clone(), label.clone())) {
+                Some(s) => Some(s),
+                None => match self.range(state, lo) {
+                    Some(r) if *hi <= *r.1 => Some(r.2),
+                    _ => self.transitions.get(&(state.clone(), Anything))
+                }
+            },
+            Anything => self.transitions.get(&(state.clone(), Anything)),
+            Epsilon => None
+        }
+    }
+}
+
+// The distinct labels among `labels`, leaving out `Epsilon`
+fn distinct_labels<'a, I: Eq + Hash + Clone + 'a, T>(labels: T) -> Vec<Transition<I>>
+        where T: IntoIterator<Item = &'a Transition<I>> {
+    let mut seen = HashSet::new();
+    labels.into_iter().filter(|l| **l != Epsilon && seen.insert(*l)).cloned().collect()
+}
+
+// One `Input` probe per interval, which behaves like every other symbol of its interval
+fn interval_probes<I: Clone>(intervals: &[(I, I)]) -> Vec<Transition<I>> {
+    intervals.iter().map(|&(ref lo, _)| Input(lo.clone())).collect()
+}
+
+// Builds the machine reachable from `start`, where `step` gives the configuration reached on the
+// symbols of each of `labels`, or `None` if they're rejected. An edge is only written when its
+// target differs from the one the symbols would fall back to: a covering `Range` or `Anything`
+// for an `Input`, and `Anything` for a `Range`. Rejected symbols that would otherwise fall back
+// to a live target are sent to a dead state, which has no edges.
+fn build_dfa<C, I, F, A>(start: Option<C>, labels: &[Transition<I>], step: F, accept: A) -> DFA<usize, I>
+        where C: Eq + Hash + Clone, I: Eq + Hash + Clone + PartialOrd,
+              F: Fn(&C, &Transition<I>) -> Option<C>, A: Fn(&C) -> bool {
+    let start = match start {
+        Some(start) => start,
+        None => return DFA::new(0, HashSet::new(), HashMap::new())
+    };
+
+    let anything = labels.iter().position(|l| *l == Anything);
+    let fallbacks: Vec<Option<usize>> = labels.iter().map(|label| match *label {
+        Input(ref c) => labels.iter().position(|l| match *l { Range(..) => l.matches(c), _ => false })
+            .or(anything),
+        Range(..) => anything,
+        _ => None
+    }).collect();
+
+    let mut ids = HashMap::new();
+    let mut configs = vec![Some(start.clone())];
+    let mut dead = None;
+    let mut accept_states = HashSet::new();
+    let mut transitions = HashMap::new();
+    ids.insert(start, 0);
+    let mut from = 0;
+    while from < configs.len() {
+        let config = match configs[from].clone() {
+            Some(config) => config,
+            None => { from += 1; continue; }
+        };
+        if accept(&config) {
+            accept_states.insert(from);
+        }
+
+        let targets: Vec<Option<C>> = labels.iter().map(|l| step(&config, l)).collect();
+        for (i, label) in labels.iter().enumerate() {
+            let fallback = fallbacks[i].and_then(|j| targets[j].clone());
+            if targets[i] == fallback {
+                continue;
+            }
+            let to = match targets[i] {
+                Some(ref target) => match ids.get(target) {
+                    Some(&id) => id,
+                    None => {
+                        let id = configs.len();
+                        ids.insert(target.clone(), id);
+                        configs.push(Some(target.clone()));
+                        id
+                    }
+                },
+                None => match dead {
+                    Some(id) => id,
+                    None => {
+                        let id = configs.len();
+                        configs.push(None);
+                        dead = Some(id);
+                        id
+                    }
+                }
+            };
+            transitions.insert((from, label.clone()), to);
+        }
+        from += 1;
+    }
+
+    DFA::new(0, accept_states, transitions)
+}
+
+impl<'a, S: 'a + Hash + Eq + Copy, I: Hash + Eq + Copy + PartialOrd> Iterator for DFAIter<'a, S, I> {
+    type Item = &'a S;
+
+    fn next(&mut self) -> Option<&'a S> {
+        if self.done {
+            return None
+        } else if self.pos == self.input.len() {
+            self.done = true;
+            return Some(self.cur_state)
+        }
+
+        let c = self.input[self.pos];
+        match self.stepper.step(self.cur_state, &c) {
+            Some(s) => {
+                self.pos += 1;
+                let ret = self.cur_state;
+                self.cur_state = s;
+                Some(ret)
+            },
+            None => {
+                // Skip the rest of the input
+                self.done = true;
+                Some(self.cur_state)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>){
+        if self.done {
+            (0, Some(0))
+        } else {
+            (1, (self.input.len() - self.pos).checked_add(1))
+        }
+    }
+}
+
+impl<S: Eq + Hash, I: Eq + Hash> DFA<S, I> {
+    pub fn iter(&self, input: Vec<I>) -> DFAIter<S, I> {
+        DFAIter { input: input, stepper: self.stepper(), cur_state: &self.start, pos: 0, done: false }
+    }
+}
+
+impl<S: Eq + Hash + Clone, I: Eq + Hash + Clone + PartialOrd> DFA<S, I> {
+    pub fn run_until<P: Fn(&S) -> bool>(&self, input: Vec<I>, stop: P) -> Option<(usize, S)> {
+        let stepper = self.stepper();
+        let mut cur_state = &self.start;
+        if stop(cur_state) {
+            return Some((0, cur_state.clone()))
+        }
+
+        for (pos, c) in input.iter().enumerate() {
+            match stepper.step(cur_state, c) {
+                Some(s) => cur_state = s,
+                None => return None
+            }
+            if stop(cur_state) {
+                return Some((pos + 1, cur_state.clone()))
+            }
+        }
+
+        if self.accept_states.contains(cur_state) {
+            Some((input.len(), cur_state.clone()))
+        } else {
+            None
+        }
+    }
+
+    fn labels(&self) -> Vec<Transition<I>> {
+        distinct_labels(self.transitions.keys().map(|k| &k.1))
+    }
+
+    fn joint_labels<S2: Eq + Hash>(&self, other: &DFA<S2, I>) -> Vec<Transition<I>> {
+        distinct_labels(self.transitions.keys().map(|k| &k.1).chain(other.transitions.keys().map(|k| &k.1)))
+    }
+
+    fn symbols(&self) -> Vec<I> {
+        let mut symbols = Vec::new();
+        for &(_, ref trans) in self.transitions.keys() {
+            if let Input(ref c) = *trans {
+                symbols.push(c.clone());
+            }
+        }
+        symbols
+    }
+
+    // Whether every edge reachable from the start is labelled with a single input, so that the
+    // words of the machine can be spelled out from the edge labels
+    fn is_explicit_from_start(&self) -> bool {
+        let reachable = self.forward_reachable(&self.start);
+        self.transitions.keys().all(|&(ref from, ref trans)| match *trans {
+            Input(_) => true,
+            _ => !reachable.contains(from)
+        })
+    }
+
+    fn outgoing(&self) -> HashMap<&S, Vec<(&Transition<I>, &S)>> {
+        let mut outgoing = HashMap::new();
+        for (&(ref from, ref trans), to) in self.transitions.iter() {
+            outgoing.entry(from).or_insert(Vec::new()).push((trans, to));
+        }
+        outgoing
+    }
+
+    // A shortest word reaching each reachable state. Machines with reachable `Range` or `Anything`
+    // edges are rejected, since a symbol taking such an edge can't be read off its label.
+    pub fn cover_words(&self) -> Result<HashMap<S, Vec<I>>, AutomatonError> {
+        if !self.is_explicit_from_start() {
+            return Err(AutomatonError::NotExplicit)
+        }
+
+        let outgoing = self.outgoing();
+        let mut words = HashMap::new();
+        let mut queue = VecDeque::new();
+
+        words.insert(self.start.clone(), Vec::new());
+        queue.push_back(&self.start);
+        while let Some(state) = queue.pop_front() {
+            if let Some(edges) = outgoing.get(state) {
+                for &(trans, to) in edges {
+                    if let Input(ref c) = *trans {
+                        if words.contains_key(to) {
+                            continue;
+                        }
+                        let mut word = words[state].clone();
+                        word.push(c.clone());
+                        words.insert(to.clone(), word);
+                        queue.push_back(to);
+                    }
+                }
+            }
+        }
+        Ok(words)
+    }
+
+    // Both queries include the given state itself
+    pub fn forward_reachable(&self, from: &S) -> HashSet<S> {
+        let outgoing = self.outgoing();
+        let mut reachable = HashSet::new();
+        let mut queue = VecDeque::new();
+        reachable.insert(from.clone());
+        queue.push_back(from);
+        while let Some(state) = queue.pop_front() {
+            if let Some(edges) = outgoing.get(state) {
+                for &(_, to) in edges {
+                    if reachable.insert(to.clone()) {
+                        queue.push_back(to);
+                    }
+                }
+            }
+        }
+        reachable
+    }
+
+    pub fn backward_reachable(&self, to: &S) -> HashSet<S> {
+        let mut incoming = HashMap::new();
+        for (&(ref from, _), to) in self.transitions.iter() {
+            incoming.entry(to).or_insert(Vec::new()).push(from);
+        }
+
+        let mut reachable = HashSet::new();
+        let mut queue = VecDeque::new();
+        reachable.insert(to.clone());
+        queue.push_back(to);
+        while let Some(state) = queue.pop_front() {
+            if let Some(edges) = incoming.get(state) {
+                for from in edges {
+                    if reachable.insert((*from).clone()) {
+                        queue.push_back(from);
+                    }
+                }
+            }
+        }
+        reachable
+    }
+
+    pub fn residual(&self, state: &S) -> DFA<S, I> {
+        let reachable = self.forward_reachable(state);
+        let accept_states = self.accept_states.iter().filter(|s| reachable.contains(s)).cloned().collect();
+        let transitions = self.transitions.iter()
+            .filter(|&(&(ref from, _), _)| reachable.contains(from))
+            .map(|(key, to)| (key.clone(), to.clone()))
+            .collect();
+        DFA::new(state.clone(), accept_states, transitions)
+    }
+
+    // States from which some accept state can be reached
+    fn live_states(&self) -> HashSet<&S> {
+        let mut live: HashSet<&S> = self.accept_states.iter().collect();
+        loop {
+            let old_len = live.len();
+            for (&(ref from, _), to) in self.transitions.iter() {
+                if live.contains(to) {
+                    live.insert(from);
+                }
+            }
+            if old_len == live.len() {
+                break;
+            }
+        }
+        live
+    }
+
+    // Partitions the states into Myhill-Nerode classes, comparing them on where each of `probes`
+    // leads. Class 0 is the implicit dead state, which absorbs every state that can't reach an
+    // accept and is left out of the returned map.
+    fn partition_on(&self, stepper: &Stepper<S, I>, probes: &[Transition<I>]) -> HashMap<&S, usize> {
+        let live = self.live_states();
+        let states: Vec<&S> = live.into_iter().collect();
+        let index: HashMap<&S, usize> = states.iter().enumerate().map(|(i, s)| (*s, i)).collect();
+        let mut classes: Vec<usize> = states.iter()
+            .map(|s| if self.accept_states.contains(s) { 1 } else { 2 }).collect();
+
+        let mut num_classes = 0;
+        loop {
+            let mut ids = HashMap::new();
+            let mut new_classes = Vec::new();
+            for (i, state) in states.iter().enumerate() {
+                let signature: Vec<usize> = probes.iter().map(|probe| {
+                    match stepper.probe(state, probe).and_then(|s| index.get(s)) {
+                        Some(&j) => classes[j],
+                        None => 0
+                    }
+                }).collect();
+                let next_id = ids.len() + 1;
+                new_classes.push(*ids.entry((classes[i], signature)).or_insert(next_id));
+            }
+
+            classes = new_classes;
+            if ids.len() == num_classes {
+                break;
+            }
+            num_classes = ids.len();
+        }
+
+        states.into_iter().zip(classes.into_iter()).collect()
+    }
+
+    // Builds the reachable part of the product machine, dropping pairs that can never be accepted.
+    // The machines are compared label by label, see `Stepper::probe`.
+    fn product<S2, F>(&self, other: &DFA<S2, I>, accept: F) -> DFA<usize, I>
+            where S2: Eq + Hash + Clone, F: Fn(bool, bool) -> bool {
+        let stepper = self.stepper();
+        let other_stepper = other.stepper();
+        let step = |&(a, b): &(Option<&S>, Option<&S2>), label: &Transition<I>| {
+            let next = (a.and_then(|s| stepper.probe(s, label)), b.and_then(|s| other_stepper.probe(s, label)));
+            if accept(next.0.is_some(), next.1.is_some()) { Some(next) } else { None }
+        };
+        let accepts = |&(a, b): &(Option<&S>, Option<&S2>)| {
+            accept(a.map_or(false, |s| self.accept_states.contains(s)),
+                   b.map_or(false, |s| other.accept_states.contains(s)))
+        };
+
+        let start = (Some(&self.start), Some(&other.start));
+        build_dfa(Some(start), &self.joint_labels(other), step, accepts).minimize()
+    }
+
+    pub fn union_minimized<S2: Eq + Hash + Clone>(&self, other: &DFA<S2, I>) -> DFA<usize, I> {
+        self.product(other, |a, b| a || b)
+    }
+
+    pub fn intersect_minimized<S2: Eq + Hash + Clone>(&self, other: &DFA<S2, I>) -> DFA<usize, I> {
+        self.product(other, |a, b| a && b)
+    }
+
+    // A label that isn't a state of the machine is not equivalent to anything
+    pub fn are_equivalent_states(&self, a: &S, b: &S) -> bool {
+        let states = self.states();
+        if !states.contains(a) || !states.contains(b) {
+            return false
+        }
+
+        let classes = self.partition_on(&self.stepper(), &self.labels());
+        classes.get(a).cloned().unwrap_or(0) == classes.get(b).cloned().unwrap_or(0)
+    }
+
+    // States are compared label by label, see `Stepper::probe`. Each edge is only kept where it
+    // differs from the `Range` or `Anything` edge its symbols would otherwise take.
+    pub fn minimize(&self) -> DFA<usize, I> {
+        let stepper = self.stepper();
+        let labels = self.labels();
+        let classes = self.partition_on(&stepper, &labels);
+
+        let mut representatives = HashMap::new();
+        for (state, class) in classes.iter() {
+            representatives.entry(*class).or_insert(*state);
+        }
+
+        let step = |class: &usize, label: &Transition<I>| {
+            stepper.probe(representatives[class], label).and_then(|s| classes.get(s)).cloned()
+        };
+        let accept = |class: &usize| self.accept_states.contains(representatives[class]);
+        build_dfa(classes.get(&self.start).cloned(), &labels, step, accept)
+    }
+
+    // Like `minimize`, but the states are compared on the alphabet intervals, which every edge
+    // label covers entirely or not at all. The edges of each state are relabelled canonically:
+    // consecutive symbols with the same target become one `Input` or `Range` edge, and `Anything`
+    // stands for the target of the largest symbol, so however the labels are split, machines with
+    // the same language minimize to the same edges.
+    fn minimize_by_intervals(&self) -> DFA<usize, I> where I: Symbol {
+        let stepper = self.stepper();
+        let intervals = alphabet_intervals(self.transitions.keys().map(|k| &k.1));
+        let classes = self.partition_on(&stepper, &interval_probes(&intervals));
+        let class_of = |s: Option<&S>| s.and_then(|s| classes.get(s)).cloned().unwrap_or(0);
+
+        let mut representatives = HashMap::new();
+        for (state, class) in classes.iter() {
+            representatives.entry(*class).or_insert(*state);
+        }
+
+        let mut ids = HashMap::new();
+        let mut id = 0;
+        let mut get_id = |class: usize| *ids.entry(class).or_insert_with(|| { let ret = id; id += 1; ret });
+
+        let start = get_id(class_of(Some(&self.start)));
+        let mut accept_states = HashSet::new();
+        let mut transitions = HashMap::new();
+        let mut queue = VecDeque::new();
+        let mut seen = HashSet::new();
+        queue.push_back(class_of(Some(&self.start)));
+        while let Some(class) = queue.pop_front() {
+            if !seen.insert(class) {
+                continue;
+            }
+            let from = get_id(class);
+            let state = match representatives.get(&class) {
+                Some(s) => *s,
+                None => continue
+            };
+            if self.accept_states.contains(state) {
+                accept_states.insert(from);
+            }
+
+            let targets: Vec<usize> = intervals.iter().map(|&(ref lo, _)| class_of(stepper.step(state, lo))).collect();
+            let default = targets[targets.len() - 1];
+            if default != 0 {
+                transitions.insert((from, Anything), get_id(default));
+                queue.push_back(default);
+            }
+
+            let mut i = 0;
+            while i < intervals.len() {
+                let mut j = i + 1;
+                while j < intervals.len() && targets[j] == targets[i] {
+                    j += 1;
+                }
+                if targets[i] != default {
+                    let (lo, hi) = (intervals[i].0.clone(), intervals[j - 1].1.clone());
+                    let label = if lo == hi { Input(lo) } else { Range(lo, hi) };
+                    transitions.insert((from, label), get_id(targets[i]));
+                    queue.push_back(targets[i]);
+                }
+                i = j;
+            }
+        }
+
+        DFA::new(start, accept_states, transitions)
+    }
+
+    pub fn bfs_order(&self) -> Vec<S> where S: Ord, I: Ord {
+        let outgoing = self.outgoing();
+        let mut seen = HashSet::new();
+        let mut order = Vec::new();
+        let mut queue = VecDeque::new();
+        seen.insert(&self.start);
+        queue.push_back(&self.start);
+        while let Some(state) = queue.pop_front() {
+            order.push(state.clone());
+            let mut edges = outgoing.get(state).cloned().unwrap_or(Vec::new());
+            edges.sort();
+            for (_, to) in edges {
+                if seen.insert(to) {
+                    queue.push_back(to);
+                }
+            }
+        }
+        order
+    }
+
+    pub fn canonical_minimal(&self) -> DFA<usize, I> where I: Ord {
+        self.minimize().renumbered()
+    }
+
+    // Exact for `Range` edges however they overlap, see `minimize_by_intervals`
+    pub fn canonical_minimal_by_intervals(&self) -> DFA<usize, I> where I: Symbol {
+        self.minimize_by_intervals().renumbered()
+    }
+
+    // Numbers the states in `bfs_order`
+    fn renumbered(&self) -> DFA<usize, I> where S: Ord, I: Ord {
+        let ids: HashMap<S, usize> = self.bfs_order().into_iter().enumerate().map(|(i, s)| (s, i)).collect();
+        let accept_states = self.accept_states.iter().filter_map(|s| ids.get(s).cloned()).collect();
+        let transitions = self.transitions.iter()
+            .filter(|&(&(ref from, _), _)| ids.contains_key(from))
+            .map(|(&(ref from, ref trans), to)| ((ids[from], trans.clone()), ids[to])).collect();
+        DFA::new(0, accept_states, transitions)
+    }
+
+    // Language equality: whether both machines accept exactly the same words, regardless of how
+    // their states are labelled or laid out. See `same_structure` for structural equality. The
+    // machines are compared label by label, see `Stepper::probe`.
+    pub fn equivalent<S2: Eq + Hash + Clone>(&self, other: &DFA<S2, I>) -> bool {
+        self.agree_on(other, &self.joint_labels(other), usize::MAX)
+    }
+
+    // Exact for `Range` edges however they overlap, e.g. `Range('a', 'c')` against separate edges
+    // on 'a', 'b' and 'c'
+    pub fn equivalent_by_intervals<S2: Eq + Hash + Clone>(&self, other: &DFA<S2, I>) -> bool where I: Symbol {
+        let labels = self.transitions.keys().map(|k| &k.1).chain(other.transitions.keys().map(|k| &k.1));
+        self.agree_on(other, &interval_probes(&alphabet_intervals(labels)), usize::MAX)
+    }
+
+    // Whether both machines accept the same words of at most `max_len` of `probes`
+    fn agree_on<S2: Eq + Hash + Clone>(&self, other: &DFA<S2, I>, probes: &[Transition<I>], max_len: usize)
+                                       -> bool {
+        let stepper = self.stepper();
+        let other_stepper = other.stepper();
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        seen.insert((Some(&self.start), Some(&other.start)));
+        queue.push_back((Some(&self.start), Some(&other.start), 0));
+        while let Some((a, b, depth)) = queue.pop_front() {
+            let accept_a = a.map_or(false, |s| self.accept_states.contains(s));
+            let accept_b = b.map_or(false, |s| other.accept_states.contains(s));
+            if accept_a != accept_b {
+                return false
+            }
+            if depth == max_len || (a.is_none() && b.is_none()) {
+                continue;
+            }
+            for probe in probes.iter() {
+                let next = (a.and_then(|s| stepper.probe(s, probe)), b.and_then(|s| other_stepper.probe(s, probe)));
+                if seen.insert(next) {
+                    queue.push_back((next.0, next.1, depth + 1));
+                }
+            }
+        }
+        true
+    }
+
+    // Enumerating every word needs every symbol on an edge of its own, so machines with `Range` or
+    // `Anything` edges reachable from the start are rejected.
+    pub fn shortest_accepted_all(&self) -> Result<Vec<Vec<I>>, AutomatonError> where I: Ord {
+        if !self.is_explicit_from_start() {
+            return Err(AutomatonError::NotExplicit)
+        }
+        let stepper = self.stepper();
+
+        let mut symbols = self.symbols();
+        symbols.sort();
+        symbols.dedup();
+
+        let mut predecessors = HashMap::new();
+        for from in self.states() {
+            for c in symbols.iter() {
+                if let Some(to) = stepper.step(from, c) {
+                    predecessors.entry(to).or_insert(Vec::new()).push(from);
+                }
+            }
+        }
+
+        // Distance from each state to the nearest accept state
+        let mut dist = HashMap::new();
+        let mut queue = VecDeque::new();
+        for s in self.accept_states.iter() {
+            dist.insert(s, 0);
+            queue.push_back(s);
+        }
+        while let Some(state) = queue.pop_front() {
+            let d = dist[state];
+            if let Some(preds) = predecessors.get(state) {
+                for from in preds {
+                    if !dist.contains_key(from) {
+                        dist.insert(*from, d + 1);
+                        queue.push_back(*from);
+                    }
+                }
+            }
+        }
+
+        let mut words = Vec::new();
+        let mut stack = Vec::new();
+        if dist.contains_key(&self.start) {
+            stack.push((&self.start, Vec::new()));
+        }
+        while let Some((state, word)) = stack.pop() {
+            let d = dist[state];
+            if d == 0 {
+                words.push(word);
+                continue;
+            }
+            for c in symbols.iter().rev() {
+                if let Some(to) = stepper.step(state, c) {
+                    if dist.get(to) == Some(&(d - 1)) {
+                        let mut next = word.clone();
+                        next.push(c.clone());
+                        stack.push((to, next));
+                    }
+                }
+            }
+        }
+        Ok(words)
+    }
+
+    pub fn complete_with(&self, trap: S) -> Result<DFA<S, I>, AutomatonError> {
+        if self.states().contains(&trap) {
+            return Err(AutomatonError::TrapStateExists)
+        }
+
+        let mut transitions = self.transitions.clone();
+        for state in self.states().into_iter().chain(Some(&trap)) {
+            if !transitions.contains_key(&(state.clone(), Anything)) {
+                transitions.insert((state.clone(), Anything), trap.clone());
+            }
+        }
+        Ok(DFA::new(self.start.clone(), self.accept_states.clone(), transitions))
+    }
+
+    // Panic-mode recovery: a symbol with no transition leads to `trap`, which skips everything up
+    // to the next `sync` symbol and then resumes at the start. Entering a state that can't reach an
+    // accept state fails the same way. A `sync` symbol that fails resumes at the start right away.
+    pub fn with_sync_recovery(&self, sync: &HashSet<I>, trap: S) -> Result<DFA<S, I>, AutomatonError> {
+        let states = self.states();
+        if states.contains(&trap) {
+            return Err(AutomatonError::TrapStateExists)
+        }
+
+        let stepper = self.stepper();
+        let live = self.live_states();
+        let mut transitions = self.transitions.clone();
+        for state in states.iter() {
+            for c in sync.iter() {
+                if stepper.step(state, c).map_or(true, |to| !live.contains(to)) {
+                    transitions.insert(((*state).clone(), Input(c.clone())), self.start.clone());
+                }
+            }
+            transitions.entry(((*state).clone(), Anything)).or_insert(trap.clone());
+        }
+        for c in sync.iter() {
+            transitions.insert((trap.clone(), Input(c.clone())), self.start.clone());
+        }
+        transitions.insert((trap.clone(), Anything), trap);
+
+        Ok(DFA::new(self.start.clone(), self.accept_states.clone(), transitions))
+    }
+
+    pub fn accepts(&self, input: &[I]) -> bool {
+        let stepper = self.stepper();
+        let mut cur_state = &self.start;
+        for c in input {
+            match stepper.step(cur_state, c) {
+                Some(s) => cur_state = s,
+                None => return false
+            }
+        }
+        self.accept_states.contains(cur_state)
+    }
+
+    pub fn accepts_ignoring(&self, input: &[I], skip: &HashSet<I>) -> bool {
+        let stepper = self.stepper();
+        let mut cur_state = &self.start;
+        for c in input.iter().filter(|c| !skip.contains(c)) {
+            match stepper.step(cur_state, c) {
+                Some(s) => cur_state = s,
+                None => return false
+            }
+        }
+        self.accept_states.contains(cur_state)
+    }
+
+    // Length of the longest prefix of `input` that is accepted
+    pub fn longest_match(&self, input: &[I]) -> Option<usize> {
+        let stepper = self.stepper();
+        let mut cur_state = &self.start;
+        let mut longest = if self.accept_states.contains(cur_state) { Some(0) } else { None };
+        for (pos, c) in input.iter().enumerate() {
+            match stepper.step(cur_state, c) {
+                Some(s) => cur_state = s,
+                None => break
+            }
+            if self.accept_states.contains(cur_state) {
+                longest = Some(pos + 1);
+            }
+        }
+        longest
+    }
+
+    // Length of the shortest prefix of `input` that is accepted
+    pub fn shortest_match(&self, input: &[I]) -> Option<usize> {
+        let stepper = self.stepper();
+        let mut cur_state = &self.start;
+        if self.accept_states.contains(cur_state) {
+            return Some(0)
+        }
+        for (pos, c) in input.iter().enumerate() {
+            match stepper.step(cur_state, c) {
+                Some(s) => cur_state = s,
+                None => return None
+            }
+            if self.accept_states.contains(cur_state) {
+                return Some(pos + 1)
+            }
+        }
+        None
+    }
+
+    // Counts saturate at `u64::MAX`. Machines with `Range` or `Anything` edges reachable from the
+    // start are rejected, since their words can't be counted from the edge labels alone.
+    pub fn word_counts(&self, up_to: usize) -> Result<Vec<u64>, AutomatonError> {
+        if !self.is_explicit_from_start() {
+            return Err(AutomatonError::NotExplicit)
+        }
+        let stepper = self.stepper();
+
+        let mut symbols = Vec::new();
+        for &(_, ref trans) in self.transitions.keys() {
+            if let Input(ref c) = *trans {
+                if !symbols.contains(c) {
+                    symbols.push(c.clone());
+                }
+            }
+        }
+
+        let mut counts = Vec::new();
+        let mut paths = HashMap::new();
+        paths.insert(&self.start, 1u64);
+        for _ in 0..(up_to + 1) {
+            counts.push(paths.iter().filter(|&(s, _)| self.accept_states.contains(*s))
+                        .fold(0u64, |acc, (_, n)| acc.saturating_add(*n)));
+
+            let mut next = HashMap::new();
+            for (state, n) in paths.iter() {
+                for c in symbols.iter() {
+                    if let Some(to) = stepper.step(state, c) {
+                        let count = next.entry(to).or_insert(0u64);
+                        *count = count.saturating_add(*n);
+                    }
+                }
+            }
+            paths = next;
+        }
+        Ok(counts)
+    }
+
+    // Sum of the lengths of all accepted words, or `None` when the language is infinite. Words are
+    // spelled out from the `Input` edges, so machines with an `Anything` or `Range` edge between
+    // useful states are rejected.
+    pub fn min_total_accept_length(&self) -> Result<Option<usize>, AutomatonError> {
+        let stepper = self.stepper();
+        let reachable = self.forward_reachable(&self.start);
+        let live = self.live_states();
+        let useful = |s: &S| reachable.contains(s) && live.contains(s);
+        if !useful(&self.start) {
+            return Ok(Some(0))
+        }
+
+        let mut symbols = Vec::new();
+        for (&(ref from, ref trans), to) in self.transitions.iter() {
+            match *trans {
+                Input(ref c) => if !symbols.contains(c) { symbols.push(c.clone()) },
+                _ => if useful(from) && useful(to) { return Err(AutomatonError::NotExplicit) }
+            }
+        }
+
+        let mut successors = HashMap::new();
+        let mut in_degree: HashMap<&S, usize> = HashMap::new();
+        for state in reachable.iter().filter(|s| live.contains(s)) {
+            in_degree.entry(state).or_insert(0);
+            let next: Vec<&S> = symbols.iter().filter_map(|c| stepper.step(state, c)).filter(|s| useful(s)).collect();
+            for to in next.iter() {
+                *in_degree.entry(to).or_insert(0) += 1;
+            }
+            successors.insert(state, next);
+        }
+
+        // A cycle among the useful states means infinitely many accepted words
+        let mut order = Vec::new();
+        let mut queue: VecDeque<&S> = in_degree.iter().filter(|&(_, d)| *d == 0).map(|(s, _)| *s).collect();
+        while let Some(state) = queue.pop_front() {
+            order.push(state);
+            for to in successors[state].iter() {
+                let d = in_degree.get_mut(to).unwrap();
+                *d -= 1;
+                if *d == 0 {
+                    queue.push_back(to);
+                }
+            }
+        }
+        if order.len() != successors.len() {
+            return Ok(None)
+        }
+
+        // Number of accepted words from each state, and the sum of their lengths
+        let mut words: HashMap<&S, (usize, usize)> = HashMap::new();
+        for state in order.into_iter().rev() {
+            let mut count: usize = if self.accept_states.contains(state) { 1 } else { 0 };
+            let mut total = 0usize;
+            for to in successors[state].iter() {
+                let (c, t) = words[to];
+                count = count.saturating_add(c);
+                total = total.saturating_add(t).saturating_add(c);
+            }
+            words.insert(state, (count, total));
+        }
+        Ok(Some(words[&self.start].1))
+    }
+
+    pub fn count_words(&self, len: usize) -> Result<u64, AutomatonError> {
+        self.word_counts(len).map(|counts| counts[len])
+    }
+
+    pub fn is_viable_prefix(&self, prefix: &[I]) -> bool {
+        let stepper = self.stepper();
+        let mut cur_state = &self.start;
+        for c in prefix {
+            match stepper.step(cur_state, c) {
+                Some(s) => cur_state = s,
+                None => return false
+            }
+        }
+        self.live_states().contains(cur_state)
+    }
+
+    pub fn validator(&self) -> IncrementalValidator<S, I> {
+        IncrementalValidator { dfa: self, stepper: self.stepper(), live: self.live_states(),
+                               cur_state: Some(&self.start) }
+    }
+
+    pub fn executor(&self) -> DFAExecutor<S, I> {
+        DFAExecutor { dfa: self, stepper: self.stepper(), cur_state: Some(&self.start) }
+    }
+
+    pub fn into_arc_matcher(self) -> Arc<ThreadSafeDfa<S, I>> {
+        Arc::new(ThreadSafeDfa { dfa: self })
+    }
+
+    // `equiv` is closed under reflexivity, symmetry and transitivity before merging. Merging states
+    // can make the machine nondeterministic, so the merged machine is determinized again before it
+    // is returned. The resulting language is a superset of the original one.
+    pub fn quotient<F: Fn(&S, &S) -> bool>(&self, equiv: F) -> DFA<usize, I> {
+        let states: Vec<&S> = self.states().into_iter().collect();
+        let mut parent: Vec<usize> = (0..states.len()).collect();
+        fn find(parent: &mut Vec<usize>, i: usize) -> usize {
+            let mut root = i;
+            while parent[root] != root {
+                root = parent[root];
+            }
+            parent[i] = root;
+            root
+        }
+
+        for i in 0..states.len() {
+            for j in (i + 1)..states.len() {
+                if equiv(states[i], states[j]) || equiv(states[j], states[i]) {
+                    let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+                    parent[a] = b;
+                }
+            }
+        }
+
+        let mut class = HashMap::new();
+        let mut members = HashMap::new();
+        for (i, state) in states.iter().enumerate() {
+            let root = find(&mut parent, i);
+            class.insert(*state, root);
+            members.entry(root).or_insert(Vec::new()).push(*state);
+        }
+
+        // The merged machine is determinized by following the sets of classes it can be in, each
+        // member state stepping by its own edges
+        let stepper = self.stepper();
+        let step = |classes: &BTreeSet<usize>, label: &Transition<I>| {
+            let next: BTreeSet<usize> = classes.iter().flat_map(|c| members[c].iter())
+                .filter_map(|s| stepper.probe(s, label)).map(|s| class[s]).collect();
+            if next.is_empty() { None } else { Some(next) }
+        };
+        let accept = |classes: &BTreeSet<usize>| {
+            classes.iter().any(|c| members[c].iter().any(|s| self.accept_states.contains(s)))
+        };
+
+        let start: BTreeSet<usize> = Some(class[&self.start]).into_iter().collect();
+        build_dfa(Some(start), &self.labels(), step, accept)
+    }
+
+    pub fn compile_sorted(&self) -> SortedDfa<S, I> where I: Ord {
+        let states: Vec<S> = self.states().into_iter().cloned().collect();
+        let index: HashMap<&S, usize> = states.iter().enumerate().map(|(i, s)| (s, i)).collect();
+
+        let mut edges = vec![Vec::new(); states.len()];
+        let mut ranges = vec![Vec::new(); states.len()];
+        let mut anything = vec![None; states.len()];
+        for (&(ref from, ref trans), to) in self.transitions.iter() {
+            let (from, to) = (index[from], index[to]);
+            match *trans {
+                Input(ref c) => edges[from].push((c.clone(), to)),
+                Range(ref lo, ref hi) => ranges[from].push((lo.clone(), hi.clone(), to)),
+                Anything => anything[from] = Some(to),
+                Epsilon => {}
+            }
+        }
+        for e in edges.iter_mut() {
+            e.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+
+        let accept = states.iter().map(|s| self.accept_states.contains(s)).collect();
+        SortedDfa { start: index[&self.start], states: states, accept: accept,
+                    edges: edges, ranges: ranges, anything: anything }
+    }
+
+    // Positions count the symbols read so far, so 0 compares the start states
+    pub fn diff_run<S2: Eq + Hash + Clone>(&self, other: &DFA<S2, I>, input: &[I]) -> Option<usize> {
+        let stepper = self.stepper();
+        let other_stepper = other.stepper();
+        let mut a = Some(&self.start);
+        let mut b = Some(&other.start);
+        for pos in 0..(input.len() + 1) {
+            let status_a = a.map(|s| self.accept_states.contains(s));
+            let status_b = b.map(|s| other.accept_states.contains(s));
+            if status_a != status_b {
+                return Some(pos)
+            }
+            if pos < input.len() {
+                a = a.and_then(|s| stepper.step(s, &input[pos]));
+                b = b.and_then(|s| other_stepper.step(s, &input[pos]));
+            }
+        }
+        None
+    }
+
+    // `f` should be injective on the symbols of the machine, otherwise edges that used to be
+    // distinct can collapse into one.
+    pub fn map_symbols<J: Eq + Hash + Clone, F: Fn(&I) -> J>(&self, f: F) -> DFA<S, J> {
+        match self.try_map_symbols(|c| Ok::<J, ()>(f(c))) {
+            Ok(dfa) => dfa,
+            Err(()) => unreachable!()
+        }
+    }
+
+    pub fn try_map_symbols<J: Eq + Hash + Clone, E, F: Fn(&I) -> Result<J, E>>(&self, f: F)
+                                                                               -> Result<DFA<S, J>, E> {
+        let mut transitions = HashMap::new();
+        for (&(ref from, ref trans), to) in self.transitions.iter() {
+            let trans = match *trans {
+                Input(ref c) => Input(f(c)?),
+                Range(ref lo, ref hi) => Range(f(lo)?, f(hi)?),
+                Anything => Anything,
+                Epsilon => Epsilon
+            };
+            transitions.insert((from.clone(), trans), to.clone());
+        }
+        Ok(DFA::new(self.start.clone(), self.accept_states.clone(), transitions))
     }
 
-    pub fn get_accept_states(&self) -> &HashSet<S> {
-        &self.accept_states
+    // The machines are compared label by label, see `Stepper::probe`
+    pub fn equivalent_up_to<S2: Eq + Hash + Clone>(&self, other: &DFA<S2, I>, max_len: usize) -> bool {
+        self.agree_on(other, &self.joint_labels(other), max_len)
     }
 
-    pub fn get_start_state(&self) -> &S {
-        &self.start
+    // Stops at the last state reached if the input can't be consumed entirely
+    pub fn run_states(&self, input: Vec<I>) -> Vec<S> {
+        let stepper = self.stepper();
+        let mut cur_state = &self.start;
+        let mut states = vec![cur_state.clone()];
+        for c in input.iter() {
+            match stepper.step(cur_state, c) {
+                Some(s) => cur_state = s,
+                None => break
+            }
+            states.push(cur_state.clone());
+        }
+        states
     }
 
-    pub fn get_transitions(&self) -> &HashMap<(S, Transition<I>), S> {
-        &self.transitions
+    // The edges taken while running each input of `corpus`, up to where the input gets stuck
+    pub fn coverage(&self, corpus: &[Vec<I>]) -> HashSet<(S, Transition<I>)> {
+        let stepper = self.stepper();
+        let mut covered = HashSet::new();
+        for input in corpus {
+            let mut cur_state = &self.start;
+            for c in input {
+                match stepper.step_edge(cur_state, c) {
+                    Some((trans, s)) => {
+                        covered.insert((cur_state.clone(), trans));
+                        cur_state = s;
+                    }
+                    None => break
+                }
+            }
+        }
+        covered
     }
-}
 
-impl<'a, S: 'a + Hash + Eq + Copy, I: Hash + Eq + Copy> Iterator for DFAIter<'a, S, I> {
-    type Item = &'a S;
+    pub fn uncovered_edges(&self, corpus: &[Vec<I>]) -> Vec<(S, Transition<I>, S)> {
+        let covered = self.coverage(corpus);
+        self.transitions.iter()
+            .filter(|&(key, _)| !covered.contains(key))
+            .map(|(&(ref from, ref trans), to)| (from.clone(), trans.clone(), to.clone()))
+            .collect()
+    }
 
-    fn next(&mut self) -> Option<&'a S> {
-        if self.pos > self.input.len() {
-            None
-        } else if self.pos == self.input.len() {
-            self.pos += 1;
-            Some(self.cur_state)
-        } else {
-            let c = self.input[self.pos];
+    // The counter saturates one past the upper bound, since every longer word is rejected alike.
+    // Without an upper bound it saturates at the lower one instead, as every longer word is accepted.
+    pub fn with_length_in(&self, range: RangeInclusive<usize>) -> DFA<(S, usize), I> {
+        let (lo, hi) = (*range.start(), *range.end());
+        let cap = hi.checked_add(1).unwrap_or(lo);
+        let outgoing = self.outgoing();
 
-            match self.transitions.get(&(*self.cur_state, Input(c))) {
-                Some(s) => {
-                    self.pos += 1;
-                    let ret = self.cur_state;
-                    self.cur_state = &s;
-                    Some(ret)
-                },
-                None => {
-                    // Watch out for overflow
-                    assert!(self.input.len() < self.input.len() + 1);
-
-                    match self.transitions.get(&(*self.cur_state, Anything)) {
-                        Some(s) => {
-                            self.pos += 1;
-                            let ret = self.cur_state;
-                            self.cur_state = &s;
-                            Some(ret)
-                        },
-                        None => {
-                            assert!(self.input.len() < self.input.len() + 1);
-
-                            // Skip the rest of the input
-                            self.pos = self.input.len() + 1;
-
-                            Some(self.cur_state)
-                        }
+        let mut seen = HashSet::new();
+        let mut accept_states = HashSet::new();
+        let mut transitions = HashMap::new();
+        let mut queue = VecDeque::new();
+        seen.insert((&self.start, 0));
+        queue.push_back((&self.start, 0));
+        while let Some((state, len)) = queue.pop_front() {
+            if self.accept_states.contains(state) && lo <= len && len <= hi {
+                accept_states.insert((state.clone(), len));
+            }
+            if let Some(edges) = outgoing.get(state) {
+                let next_len = cmp::min(len + 1, cap);
+                for &(trans, to) in edges {
+                    transitions.insert(((state.clone(), len), trans.clone()), (to.clone(), next_len));
+                    if seen.insert((to, next_len)) {
+                        queue.push_back((to, next_len));
                     }
                 }
             }
         }
+
+        DFA::new((self.start.clone(), 0), accept_states, transitions)
     }
 
-    fn size_hint(&self) -> (usize, Option<usize>){
-        (self.input.len(), Some(self.input.len()))
+    pub fn run_mapped<J, F: Fn(J) -> Option<I>>(&self, input: Vec<J>, map: F) -> Option<S> {
+        let stepper = self.stepper();
+        let mut cur_state = &self.start;
+        for j in input {
+            let c = match map(j) {
+                Some(c) => c,
+                None => return None
+            };
+            match stepper.step(cur_state, &c) {
+                Some(s) => cur_state = s,
+                None => return None
+            }
+        }
+
+        if self.accept_states.contains(cur_state) {
+            Some(cur_state.clone())
+        } else {
+            None
+        }
     }
 }
 
-impl<S: Eq + Hash, I: Eq + Hash> DFA<S, I> {
-    pub fn iter(&self, input: Vec<I>) -> DFAIter<S, I> {
-        DFAIter { input: input, transitions: &self.transitions, cur_state: &self.start, pos: 0 }
+impl<'a, S: Eq + Hash + Clone, I: Eq + Hash + Clone + PartialOrd> DFAExecutor<'a, S, I> {
+    pub fn feed(&mut self, symbol: I) -> Option<&'a S> {
+        self.cur_state = match self.cur_state {
+            Some(s) => self.stepper.step(s, &symbol),
+            None => None
+        };
+        self.cur_state
+    }
+
+    // Reports the accept state reached by every step that lands in one, including a self-loop that
+    // stays in it, so each accepted prefix is reported once
+    pub fn feed_reporting(&mut self, symbol: I) -> Option<&'a S> {
+        match self.feed(symbol) {
+            Some(s) if self.dfa.accept_states.contains(s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn is_accepting(&self) -> bool {
+        match self.cur_state {
+            Some(s) => self.dfa.accept_states.contains(s),
+            None => false
+        }
+    }
+
+    pub fn get_state(&self) -> Option<&'a S> {
+        self.cur_state
+    }
+}
+
+impl<'a, S: Eq + Hash + Clone, I: Eq + Hash + Clone + PartialOrd> IncrementalValidator<'a, S, I> {
+    pub fn push(&mut self, symbol: I) -> PushResult {
+        self.cur_state = match self.cur_state {
+            Some(s) if self.live.contains(s) => self.stepper.step(s, &symbol),
+            _ => None
+        };
+        self.status()
+    }
+
+    pub fn status(&self) -> PushResult {
+        match self.cur_state {
+            Some(s) if self.dfa.accept_states.contains(s) => PushResult::Accepted,
+            Some(s) if self.live.contains(s) => PushResult::Viable,
+            _ => PushResult::Dead
+        }
+    }
+}
+
+// An immutable compiled matcher. Matching never mutates the matcher, so a single instance can be
+// shared between threads behind an `Arc`.
+#[derive(Debug, Clone)]
+pub struct ThreadSafeDfa<S: Eq + Hash = usize, I: Eq + Hash = char> {
+    dfa: DFA<S, I>
+}
+
+impl<S: Eq + Hash + Clone, I: Eq + Hash + Clone + PartialOrd> ThreadSafeDfa<S, I> {
+    pub fn accepts(&self, input: &[I]) -> bool {
+        self.dfa.accepts(input)
+    }
+
+    pub fn get_dfa(&self) -> &DFA<S, I> {
+        &self.dfa
+    }
+}
+
+// A DFA whose outgoing edges are stored per state as a vector sorted by symbol, which is looked up
+// with a binary search. This is more compact than the hash table for large, sparse alphabets.
+#[derive(Debug, Clone)]
+pub struct SortedDfa<S, I> {
+    start: usize,
+    states: Vec<S>,
+    accept: Vec<bool>,
+    edges: Vec<Vec<(I, usize)>>,
+    ranges: Vec<Vec<(I, I, usize)>>,
+    anything: Vec<Option<usize>>
+}
+
+impl<S, I: Ord> SortedDfa<S, I> {
+    fn step(&self, state: usize, c: &I) -> Option<usize> {
+        match self.edges[state].binary_search_by(|e| e.0.cmp(c)) {
+            Ok(i) => Some(self.edges[state][i].1),
+            Err(_) => match self.ranges[state].iter().find(|r| r.0 <= *c && *c <= r.1) {
+                Some(r) => Some(r.2),
+                None => self.anything[state]
+            }
+        }
+    }
+
+    pub fn run(&self, input: &[I]) -> Option<&S> {
+        let mut cur_state = self.start;
+        for c in input {
+            match self.step(cur_state, c) {
+                Some(s) => cur_state = s,
+                None => return None
+            }
+        }
+        if self.accept[cur_state] {
+            Some(&self.states[cur_state])
+        } else {
+            None
+        }
+    }
+
+    pub fn accepts(&self, input: &[I]) -> bool {
+        self.run(input).is_some()
+    }
+}
+
+// Wraps a DFA so that equality and hashing compare the recognized language rather than the
+// structure of the machine.
+#[derive(Debug, Clone)]
+pub struct ByLanguage<S: Eq + Hash = usize, I: Eq + Hash = char>(pub DFA<S, I>);
+
+impl<S: Eq + Hash + Clone, I: Eq + Hash + Clone + Ord> PartialEq for ByLanguage<S, I> {
+    fn eq(&self, other: &ByLanguage<S, I>) -> bool {
+        self.0.equivalent(&other.0)
+    }
+}
+
+impl<S: Eq + Hash + Clone, I: Eq + Hash + Clone + Ord> Eq for ByLanguage<S, I> {}
+
+impl<S: Eq + Hash + Clone, I: Eq + Hash + Clone + Ord> Hash for ByLanguage<S, I> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let min = self.0.canonical_minimal();
+        let accept_states = min.accept_states_sorted();
+        let mut transitions: Vec<_> = min.transitions.into_iter().collect();
+        transitions.sort();
+        accept_states.hash(state);
+        transitions.hash(state);
     }
 }
 
-impl<S, I> Automaton for DFA<S, I> where S: Hash + Eq + Copy, I: Hash + Eq + Copy {
+impl<S, I> Automaton for DFA<S, I> where S: Hash + Eq + Copy, I: Hash + Eq + Copy + PartialOrd {
     type State = S;
     type Alphabet = I;
 
     fn run(&self, s: Vec<I>) -> Option<Vec<I>> {
+        let stepper = self.stepper();
         let mut cur_state = self.start;
         let mut path = Vec::<I>::new();
 
         for c in s {
-            match self.transitions.get(&(cur_state, Input(c))) {
+            match stepper.step(&cur_state, &c) {
                 Some(s) => {
                     cur_state = *s;
                     path.push(c);
                 }
-                None => {
-                    match self.transitions.get(&(cur_state, Anything)) {
-                        Some(s) => {
-                            cur_state = *s;
-                            path.push(c);
-                        }
-                        None => return None
-                    }
-                }
+                None => return None
             }
         }
         if self.accept_states.contains(&cur_state) {
@@ -131,7 +1446,12 @@ impl<S, I> Automaton for DFA<S, I> where S: Hash + Eq + Copy, I: Hash + Eq + Cop
 #[cfg(test)]
 mod test {
     use Automaton;
-    use dfa::DFA;
+    use dfa::{DFA, ByLanguage, PushResult};
+    use nfa::NFA;
+    use error::AutomatonError;
+    use nfa::Transition::{Input, Range, Anything};
+    use std::collections::HashSet;
+    use std::thread;
 
     macro_rules! set {
         ($($elem:expr),*) => ({
@@ -151,18 +1471,20 @@ mod test {
 
     #[test]
     fn test_dfa() {
-        let transitions = map!((0, 'a') => 0, (0, 'b') => 1, (1, 'a') => 0, (1, 'b') => 2);
+        let transitions = map!((0, Input('a')) => 0, (0, Input('b')) => 1,
+                               (1, Input('a')) => 0, (1, Input('b')) => 2);
         let dfa = DFA::new(0, set!(2), transitions);
         assert_eq!(dfa.run("aaaaa".chars().collect()), None);
         assert_eq!(dfa.run("aabaa".chars().collect()), None);
         assert_eq!(dfa.run("aababbb".chars().collect()), None);
-        assert_eq!(dfa.run("aababb".chars().collect()), Some(2));
-        assert_eq!(dfa.run("aabb".chars().collect()), Some(2));
+        assert_eq!(dfa.run("aababb".chars().collect()), Some("aababb".chars().collect()));
+        assert_eq!(dfa.run("aabb".chars().collect()), Some("aabb".chars().collect()));
     }
 
     #[test]
     fn test_iter() {
-        let transitions = map!((0, 'a') => 0, (0, 'b') => 1, (1, 'a') => 0, (1, 'b') => 2);
+        let transitions = map!((0, Input('a')) => 0, (0, Input('b')) => 1,
+                               (1, Input('a')) => 0, (1, Input('b')) => 2);
         let dfa = DFA::new(0, set!(2), transitions);
         let mut it = dfa.iter("aababbb".chars().collect());
         assert_eq!(it.next(), Some(&0));
@@ -176,4 +1498,637 @@ mod test {
         assert_eq!(it.next(), None);
         assert_eq!(it.next(), None);
     }
+
+    #[test]
+    fn test_coalesce_ranges() {
+        let transitions = map!((0, Input('a')) => 1, (0, Input('b')) => 1, (0, Input('c')) => 1,
+                               (0, Input('e')) => 1, (1, Input('d')) => 0);
+        let dfa = DFA::new(0, set!(1), transitions).coalesce_ranges();
+        let expected = map!((0, Range('a', 'c')) => 1, (0, Input('e')) => 1, (1, Input('d')) => 0);
+        assert_eq!(dfa.get_transitions(), &expected);
+        assert_eq!(dfa.run("b".chars().collect()), Some(vec!['b']));
+        assert_eq!(dfa.run("bdc".chars().collect()), Some(vec!['b', 'd', 'c']));
+        assert_eq!(dfa.run("d".chars().collect()), None);
+
+        // Edges added to the public field in place are followed too
+        let mut dfa = DFA { start: 0, accept_states: set!(1), transitions: map!((0, Input('a')) => 1) };
+        dfa.transitions.insert((0, Range('a', 'c')), 1);
+        assert!(dfa.accepts(&['b']));
+        assert_eq!(dfa.iter(vec!['c']).collect::<Vec<_>>(), vec![&0, &1]);
+    }
+
+    #[test]
+    fn test_run_until() {
+        let transitions = map!((0, Input('a')) => 1, (1, Input('a')) => 2, (2, Input('a')) => 2,
+                               (1, Input('b')) => 3);
+        let dfa = DFA::new(0, set!(2, 3), transitions);
+        let accept = dfa.get_accept_states().clone();
+        assert_eq!(dfa.run_until("aaaa".chars().collect(), |s| accept.contains(s)), Some((2, 2)));
+        assert_eq!(dfa.run_until("ab".chars().collect(), |s| accept.contains(s)), Some((2, 3)));
+        assert_eq!(dfa.run_until("aaa".chars().collect(), |_| false), Some((3, 2)));
+        assert_eq!(dfa.run_until("a".chars().collect(), |_| false), None);
+        assert_eq!(dfa.run_until("bb".chars().collect(), |_| false), None);
+    }
+
+    #[test]
+    fn test_cover_words() {
+        let transitions = map!((0, Input('a')) => 1, (1, Input('a')) => 2, (2, Input('b')) => 3,
+                               (0, Input('b')) => 0, (2, Input('a')) => 2);
+        let dfa = DFA::new(0, set!(3), transitions);
+        let words = dfa.cover_words().unwrap();
+        assert_eq!(words.len(), 4);
+        assert_eq!(words[&0], vec![]);
+        assert_eq!(words[&1], vec!['a']);
+        assert_eq!(words[&3], vec!['a', 'a', 'b']);
+
+        let any: DFA<usize, char> = DFA::new(0, set!(1), map!((0, Anything) => 1));
+        assert_eq!(any.cover_words(), Err(AutomatonError::NotExplicit));
+        let range = DFA::new(0, set!(1), map!((0, Range('a', 'c')) => 1, (0, Input('a')) => 2, (0, Input('c')) => 2));
+        assert_eq!(range.cover_words(), Err(AutomatonError::NotExplicit));
+
+        // Edges that can't be reached from the start don't matter
+        let unreachable = DFA::new(0, set!(1), map!((0, Input('a')) => 1, (2, Anything) => 1));
+        assert_eq!(unreachable.cover_words().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_minimize() {
+        // (a|b)*b with a redundant copy of the accepting state
+        let transitions = map!((0, Input('a')) => 0, (0, Input('b')) => 1,
+                               (1, Input('a')) => 0, (1, Input('b')) => 2,
+                               (2, Input('a')) => 0, (2, Input('b')) => 1,
+                               (3, Input('a')) => 3);
+        let dfa = DFA::new(0, set!(1, 2), transitions);
+        let min = dfa.canonical_minimal();
+        let expected = map!((0, Input('a')) => 0, (0, Input('b')) => 1,
+                            (1, Input('a')) => 0, (1, Input('b')) => 1);
+        assert_eq!(min.get_start_state(), &0);
+        assert_eq!(min.get_accept_states(), &set!(1));
+        assert_eq!(min.get_transitions(), &expected);
+    }
+
+    #[test]
+    fn test_by_language() {
+        let dfa1 = DFA::new(0, set!(1), map!((0, Input('a')) => 1, (1, Anything) => 1));
+        let dfa2 = DFA::new(5, set!(6, 7), map!((5, Input('a')) => 6, (6, Input('a')) => 7,
+                                                (6, Anything) => 7, (7, Anything) => 6,
+                                                (5, Input('b')) => 8, (8, Input('b')) => 8));
+        let dfa3 = DFA::new(0, set!(1), map!((0, Input('b')) => 1, (1, Anything) => 1));
+
+        let mut set = HashSet::new();
+        set.insert(ByLanguage(dfa1));
+        set.insert(ByLanguage(dfa2));
+        assert_eq!(set.len(), 1);
+        set.insert(ByLanguage(dfa3));
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_equivalent_ranges() {
+        let range = DFA::new(0, set!(1), map!((0, Range('a', 'c')) => 1));
+        let explicit = DFA::new(0, set!(1), map!((0, Input('a')) => 1, (0, Input('b')) => 1, (0, Input('c')) => 1));
+        assert!(range.equivalent_by_intervals(&explicit));
+        assert!(explicit.equivalent_by_intervals(&explicit.coalesce_ranges()));
+        assert!(!range.equivalent_by_intervals(&DFA::new(0, set!(1), map!((0, Range('a', 'd')) => 1))));
+        assert!(!range.equivalent(&DFA::new(0, set!(1), map!((0, Range('a', 'd')) => 1))));
+
+        // Anything except 'b', written with and without a dead state
+        let any = DFA::new(0, set!(1), map!((0, Anything) => 1, (0, Input('b')) => 2));
+        let split = DFA::new(0, set!(1), map!((0, Range('\0', 'a')) => 1, (0, Range('c', ::std::char::MAX)) => 1));
+        assert!(any.equivalent_by_intervals(&split));
+        assert!(any.canonical_minimal_by_intervals().same_structure(&split.canonical_minimal_by_intervals()));
+
+        let mut set = HashSet::new();
+        set.insert(ByLanguage(range.canonical_minimal_by_intervals()));
+        set.insert(ByLanguage(explicit.coalesce_ranges().canonical_minimal_by_intervals()));
+        set.insert(ByLanguage(explicit.canonical_minimal_by_intervals()));
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn test_enum_alphabet() {
+        #[derive(Debug, Hash, Eq, PartialEq, Clone, Copy, PartialOrd, Ord)]
+        enum Sym { A, B }
+
+        // Words ending in A
+        let nfa = NFA::new(0, set!(1), map!((0, Input(Sym::A)) => set!(0, 1), (0, Input(Sym::B)) => set!(0)));
+        let dfa = DFA::new(0, set!(1), map!((0, Input(Sym::A)) => 1, (0, Input(Sym::B)) => 0,
+                                            (1, Input(Sym::A)) => 1, (1, Input(Sym::B)) => 0));
+        assert!(nfa.into_dfa().equivalent(&dfa));
+        assert!(dfa.minimize().equivalent(&dfa));
+        assert!(dfa.quotient(|_, _| false).equivalent(&dfa));
+        assert!(nfa.into_minimal_dfa().same_structure(&dfa.canonical_minimal()));
+
+        let mut set = HashSet::new();
+        set.insert(ByLanguage(dfa));
+        set.insert(ByLanguage(nfa.into_dfa()));
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn test_shortest_accepted_all() {
+        let transitions = map!((0, Input('a')) => 1, (0, Input('b')) => 2, (0, Input('c')) => 3,
+                               (1, Input('b')) => 4, (2, Input('a')) => 4, (3, Input('c')) => 3,
+                               (4, Input('a')) => 5);
+        let dfa = DFA::new(0, set!(4, 5), transitions);
+        assert_eq!(dfa.shortest_accepted_all(), Ok(vec![vec!['a', 'b'], vec!['b', 'a']]));
+
+        let dfa = DFA::new(0, set!(0), map!((0, Input('a')) => 1));
+        assert_eq!(dfa.shortest_accepted_all(), Ok(vec![vec![]]));
+
+        let dfa = DFA::new(0, set!(2), map!((0, Input('a')) => 1));
+        assert_eq!(dfa.shortest_accepted_all(), Ok(Vec::<Vec<char>>::new()));
+
+        let dfa: DFA<_, char> = DFA::new(0, set!(1), map!((0, Anything) => 1));
+        assert_eq!(dfa.shortest_accepted_all(), Err(AutomatonError::NotExplicit));
+        let dfa = DFA::new(0, set!(1), map!((0, Range('a', 'c')) => 1));
+        assert_eq!(dfa.shortest_accepted_all(), Err(AutomatonError::NotExplicit));
+
+        // An `Anything` edge that can't be reached from the start is ignored
+        let dfa = DFA::new(0, set!(1), map!((0, Input('a')) => 1, (2, Anything) => 1));
+        assert_eq!(dfa.shortest_accepted_all(), Ok(vec![vec!['a']]));
+    }
+
+    #[test]
+    fn test_complete_with() {
+        let transitions = map!(("start".to_string(), Input('a')) => "end".to_string());
+        let dfa = DFA::new("start".to_string(), set!("end".to_string()), transitions);
+        let complete = dfa.complete_with("DEAD".to_string()).unwrap();
+        assert_eq!(complete.run_until("ba".chars().collect(), |_| false), None);
+        assert_eq!(complete.run_until("ba".chars().collect(), |s| s == "DEAD"),
+                   Some((1, "DEAD".to_string())));
+        assert_eq!(complete.get_transitions().len(), 4);
+
+        assert_eq!(dfa.complete_with("end".to_string()).err(),
+                   Some(AutomatonError::TrapStateExists));
+    }
+
+    #[test]
+    fn test_run_mapped() {
+        enum Token { A, B, Other }
+        let map = |t: Token| match t {
+            Token::A => Some('a'),
+            Token::B => Some('b'),
+            Token::Other => None
+        };
+
+        let transitions = map!((0, Input('a')) => 1, (1, Input('a')) => 2, (2, Input('b')) => 3);
+        let dfa = DFA::new(0, set!(3), transitions);
+        assert_eq!(dfa.run_mapped(vec![Token::A, Token::A, Token::B], &map), Some(3));
+        assert_eq!(dfa.run_mapped(vec![Token::A, Token::B], &map), None);
+        assert_eq!(dfa.run_mapped(vec![Token::A, Token::A, Token::Other], &map), None);
+    }
+
+    #[test]
+    fn test_arc_matcher() {
+        let transitions = map!((0, Input('a')) => 1, (1, Input('a')) => 2, (2, Input('b')) => 3);
+        let matcher = DFA::new(0, set!(3), transitions).into_arc_matcher();
+
+        let handles: Vec<_> = (0..4).map(|i| {
+            let matcher = matcher.clone();
+            thread::spawn(move || {
+                let input: Vec<char> = if i % 2 == 0 { "aab" } else { "aba" }.chars().collect();
+                matcher.accepts(&input)
+            })
+        }).collect();
+        let results: Vec<bool> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(results, vec![true, false, true, false]);
+    }
+
+    #[test]
+    fn test_feed_reporting() {
+        let transitions = map!((0, Input('a')) => 1, (1, Input('a')) => 2, (2, Input('b')) => 3);
+        let dfa = DFA::new(0, set!(3), transitions);
+        let mut exec = dfa.executor();
+        assert_eq!(exec.feed_reporting('a'), None);
+        assert_eq!(exec.feed_reporting('a'), None);
+        assert_eq!(exec.feed_reporting('b'), Some(&3));
+        assert!(exec.is_accepting());
+        assert_eq!(exec.feed_reporting('b'), None);
+        assert!(!exec.is_accepting());
+
+        // a+: every 'a' completes an accepted prefix, even on the self-loop
+        let transitions = map!((0, Input('a')) => 1, (1, Input('a')) => 1, (1, Input('b')) => 2,
+                               (2, Input('a')) => 1);
+        let dfa = DFA::new(0, set!(1, 2), transitions);
+        let mut exec = dfa.executor();
+        assert_eq!(exec.feed_reporting('a'), Some(&1));
+        assert_eq!(exec.feed_reporting('a'), Some(&1));
+        assert_eq!(exec.feed_reporting('a'), Some(&1));
+        assert_eq!(exec.feed_reporting('b'), Some(&2));
+        assert_eq!(exec.feed_reporting('a'), Some(&1));
+    }
+
+    #[test]
+    fn test_quotient() {
+        let transitions = map!((0, Input('a')) => 1, (1, Input('a')) => 2, (2, Input('b')) => 3);
+        let dfa = DFA::new(0, set!(3), transitions);
+        let merged = dfa.quotient(|a, b| (*a, *b) == (1, 2));
+        assert!(merged.accepts(&['a', 'a', 'b']));
+        assert!(merged.accepts(&['a', 'b']));
+        assert!(merged.accepts(&['a', 'a', 'a', 'b']));
+        assert!(!merged.accepts(&['b']));
+        let dfa = DFA::new(0, set!(1), map!((0, Range('a', 'c')) => 1, (1, Anything) => 2));
+        let same = dfa.quotient(|_, _| false);
+        assert!(same.equivalent(&dfa));
+        assert!(same.accepts(&['b']));
+
+        // `Anything` only takes the symbols that the other edges of its state don't
+        let dfa = DFA::new(0, set!(2), map!((0, Input('a')) => 1, (0, Anything) => 2));
+        let same = dfa.quotient(|_, _| false);
+        assert!(same.equivalent(&dfa));
+        assert!(!same.accepts(&['a']));
+        assert!(same.accepts(&['z']));
+    }
+
+    #[test]
+    fn test_compile_sorted() {
+        let transitions = map!((0, Input('a')) => 0, (0, Input('b')) => 1, (0, Range('x', 'z')) => 2,
+                               (1, Input('a')) => 0, (1, Input('b')) => 2, (2, Anything) => 2);
+        let dfa = DFA::new(0, set!(2), transitions);
+        let sorted = dfa.compile_sorted();
+
+        let alphabet = ['a', 'b', 'c', 'y'];
+        let mut inputs = vec![vec![]];
+        let mut layer: Vec<Vec<char>> = vec![vec![]];
+        for _ in 0..4 {
+            layer = layer.iter().flat_map(|w| alphabet.iter().map(move |c| {
+                let mut w = w.clone();
+                w.push(*c);
+                w
+            })).collect();
+            inputs.extend(layer.iter().cloned());
+        }
+        for input in inputs.iter() {
+            assert_eq!(sorted.accepts(input), dfa.accepts(input));
+        }
+        assert_eq!(sorted.run(&['a', 'b', 'b']), Some(&2));
+    }
+
+    #[test]
+    fn test_reachable() {
+        let transitions = map!((0, Input('a')) => 0, (0, Input('b')) => 1, (1, Input('a')) => 0,
+                               (1, Input('b')) => 2, (3, Input('a')) => 2);
+        let dfa = DFA::new(0, set!(2), transitions);
+        assert_eq!(dfa.forward_reachable(&0), set!(0, 1, 2));
+        assert_eq!(dfa.forward_reachable(&2), set!(2));
+        assert_eq!(dfa.backward_reachable(&2), set!(0, 1, 2, 3));
+        assert_eq!(dfa.backward_reachable(&0), set!(0, 1));
+    }
+
+    #[test]
+    fn test_from_prefix_rules() {
+        let dfa = DFA::from_prefix_rules(&[("ab", true), ("ac", false)]).unwrap();
+        assert!(dfa.accepts(&['a', 'b']));
+        assert!(dfa.accepts(&['a', 'b', 'c', 'x']));
+        assert!(!dfa.accepts(&['a', 'c']));
+        assert!(!dfa.accepts(&['a', 'c', 'b']));
+        assert!(!dfa.accepts(&['a']));
+        assert!(!dfa.accepts(&['b']));
+        assert!(!dfa.accepts(&[]));
+
+        assert_eq!(DFA::from_prefix_rules(&[("ab", true), ("ab", false)]).err(),
+                   Some(AutomatonError::DuplicatePrefix("ab".to_string())));
+
+        // The longest matching prefix decides
+        let dfa = DFA::from_prefix_rules(&[("a", true), ("ab", false), ("abc", true)]).unwrap();
+        assert!(dfa.accepts(&['a']));
+        assert!(dfa.accepts(&['a', 'x']));
+        assert!(!dfa.accepts(&['a', 'b']));
+        assert!(!dfa.accepts(&['a', 'b', 'x']));
+        assert!(dfa.accepts(&['a', 'b', 'c', 'x']));
+
+        let dfa = DFA::from_prefix_rules(&[("", true), ("a", false)]).unwrap();
+        assert!(dfa.accepts(&[]));
+        assert!(dfa.accepts(&['b']));
+        assert!(!dfa.accepts(&['a', 'b']));
+    }
+
+    #[test]
+    fn test_diff_run() {
+        let dfa1 = DFA::new(0, set!(2), map!((0, Input('a')) => 1, (1, Input('a')) => 2, (2, Input('a')) => 2));
+        let dfa2 = DFA::new('x', set!('z'), map!(('x', Input('a')) => 'y', ('y', Input('a')) => 'y',
+                                                 ('y', Input('b')) => 'z'));
+        assert_eq!(dfa1.diff_run(&dfa2, &['a', 'a', 'a']), Some(2));
+        assert_eq!(dfa1.diff_run(&dfa2, &['a', 'b']), Some(2));
+        assert_eq!(dfa1.diff_run(&dfa2, &['a']), None);
+        assert_eq!(dfa1.diff_run(&dfa1, &['a', 'a', 'a', 'b']), None);
+    }
+
+    #[test]
+    fn test_try_map_symbols() {
+        #[derive(Debug, Hash, Eq, PartialEq, Clone, PartialOrd)]
+        enum Sym { A, B, C }
+        let to_sym = |c: &char| match *c {
+            'a' => Ok(Sym::A),
+            'b' => Ok(Sym::B),
+            'c' => Ok(Sym::C),
+            c => Err(c)
+        };
+
+        let transitions = map!((0, Input('a')) => 1, (1, Input('b')) => 2, (1, Input('c')) => 2);
+        let dfa = DFA::new(0, set!(2), transitions);
+        let mapped = dfa.try_map_symbols(&to_sym).unwrap();
+        assert!(mapped.accepts(&[Sym::A, Sym::C]));
+        assert!(!mapped.accepts(&[Sym::B]));
+
+        let transitions = map!((0, Input('a')) => 1, (1, Input('z')) => 2);
+        let dfa = DFA::new(0, set!(2), transitions);
+        assert_eq!(dfa.try_map_symbols(&to_sym).err(), Some('z'));
+    }
+
+    #[test]
+    fn test_equivalent_up_to() {
+        // a* and a* without "aaa"
+        let dfa1 = DFA::new(0, set!(0), map!((0, Input('a')) => 0));
+        let dfa2 = DFA::new(0, set!(0, 1, 2, 4), map!((0, Input('a')) => 1, (1, Input('a')) => 2,
+                                                      (2, Input('a')) => 3, (3, Input('a')) => 4,
+                                                      (4, Input('a')) => 4));
+        assert!(dfa1.equivalent_up_to(&dfa2, 0));
+        assert!(dfa1.equivalent_up_to(&dfa2, 2));
+        assert!(!dfa1.equivalent_up_to(&dfa2, 3));
+        let any: DFA<_, char> = DFA::new(0, set!(1), map!((0, Anything) => 1));
+        let a = DFA::new(0, set!(1), map!((0, Input('a')) => 1));
+        assert!(!any.equivalent_up_to(&a, 1));
+        let range = DFA::new(0, set!(1), map!((0, Range('a', 'c')) => 1));
+        let ac = DFA::new(0, set!(1), map!((0, Input('a')) => 1, (0, Input('c')) => 1));
+        assert!(!range.equivalent_up_to(&ac, 1));
+        assert!(range.equivalent_up_to(&ac, 0));
+    }
+
+    #[test]
+    fn test_accept_states_sorted() {
+        let dfa = DFA::new(0, set!(7, 2, 5, 3), map!((0, Input('a')) => 2));
+        assert_eq!(dfa.accept_states_sorted(), vec![2, 3, 5, 7]);
+    }
+
+    #[test]
+    fn test_run_states() {
+        let transitions = map!((0, Input('a')) => 1, (1, Input('a')) => 2, (2, Input('b')) => 3);
+        let dfa = DFA::new(0, set!(3), transitions);
+        assert_eq!(dfa.run_states("aab".chars().collect()), vec![0, 1, 2, 3]);
+        assert_eq!(dfa.run_states("aa".chars().collect()), vec![0, 1, 2]);
+        assert_eq!(dfa.run_states("abb".chars().collect()), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_accepts_ignoring() {
+        let dfa = DFA::new(0, set!(2), map!((0, Input('a')) => 1, (1, Input('b')) => 2));
+        let skip = set!(' ');
+        assert!(dfa.accepts_ignoring(&['a', ' ', 'b'], &skip));
+        assert!(dfa.accepts_ignoring(&[' ', 'a', 'b', ' '], &skip));
+        assert!(!dfa.accepts_ignoring(&['a', ' ', 'b'], &HashSet::new()));
+        assert!(!dfa.accepts_ignoring(&['a', '_', 'b'], &skip));
+    }
+
+    #[test]
+    fn test_are_equivalent_states() {
+        let transitions = map!((0, Input('a')) => 0, (0, Input('b')) => 1,
+                               (1, Input('a')) => 0, (1, Input('b')) => 2,
+                               (2, Input('a')) => 0, (2, Input('b')) => 1,
+                               (3, Input('a')) => 3, (4, Input('b')) => 3);
+        let dfa = DFA::new(0, set!(1, 2), transitions);
+        assert!(dfa.are_equivalent_states(&1, &2));
+        assert!(dfa.are_equivalent_states(&3, &4));
+        assert!(!dfa.are_equivalent_states(&0, &1));
+        assert!(!dfa.are_equivalent_states(&0, &3));
+        assert!(!dfa.are_equivalent_states(&3, &42));
+        assert!(!dfa.are_equivalent_states(&42, &99));
+    }
+
+    #[test]
+    fn test_union_intersect_minimized() {
+        let a = DFA::new(0, set!(1), map!((0, Input('a')) => 1));
+        let b = DFA::new(0, set!(1), map!((0, Input('b')) => 1));
+        let ab = DFA::new(0, set!(2), map!((0, Input('a')) => 1, (1, Input('b')) => 2));
+
+        let union = a.union_minimized(&b).union_minimized(&ab).union_minimized(&a);
+        assert!(union.accepts(&['a']));
+        assert!(union.accepts(&['b']));
+        assert!(union.accepts(&['a', 'b']));
+        assert!(!union.accepts(&['b', 'a']));
+        assert_eq!(union.num_states(), union.minimize().num_states());
+        assert_eq!(union.num_states(), 3);
+
+        let any = DFA::new(0, set!(1), map!((0, Anything) => 1, (1, Anything) => 1));
+        let intersection = any.intersect_minimized(&ab);
+        assert!(intersection.accepts(&['a', 'b']));
+        assert!(!intersection.accepts(&['a']));
+        assert_eq!(intersection.num_states(), 3);
+        let range = DFA::new(0, set!(1), map!((0, Range('a', 'c')) => 1));
+        let z = DFA::new(0, set!(1), map!((0, Input('z')) => 1));
+        let union = range.union_minimized(&z);
+        assert!(union.accepts(&['b']));
+        assert!(union.accepts(&['z']));
+        assert!(!union.accepts(&['d']));
+        assert!(range.intersect_minimized(&range).equivalent(&range));
+        assert!(range.intersect_minimized(&any).accepts(&['b']));
+    }
+
+    #[test]
+    fn test_uncovered_edges() {
+        let transitions = map!((0, Input('a')) => 1, (1, Input('b')) => 2, (1, Range('c', 'e')) => 2);
+        let dfa = DFA::new(0, set!(2), transitions);
+        assert_eq!(dfa.uncovered_edges(&[vec!['a', 'b']]), vec![(1, Range('c', 'e'), 2)]);
+        assert_eq!(dfa.uncovered_edges(&[vec!['a', 'b'], vec!['a', 'd']]), vec![]);
+    }
+
+    #[test]
+    fn test_with_length_in() {
+        let dfa = DFA::new(0, set!(0), map!((0, Input('a')) => 0, (0, Input('b')) => 0));
+        let bounded = dfa.with_length_in(2..=3);
+        assert!(!bounded.accepts(&['a']));
+        assert!(bounded.accepts(&['a', 'b']));
+        assert!(bounded.accepts(&['a', 'b', 'a']));
+        assert!(!bounded.accepts(&['a', 'b', 'a', 'b']));
+        assert!(!bounded.accepts(&['a', 'b', 'a', 'b', 'a']));
+        assert!(!bounded.accepts(&['a', 'c']));
+        assert_eq!(bounded.num_states(), 5);
+        let unbounded = dfa.with_length_in(0..=::std::usize::MAX);
+        assert!(unbounded.accepts(&[]));
+        assert!(unbounded.accepts(&['a', 'b', 'a', 'b', 'a']));
+        assert!(!unbounded.accepts(&['c']));
+    }
+
+    #[test]
+    fn test_bfs_order() {
+        let transitions = map!((0, Input('b')) => 1, (0, Input('a')) => 2, (2, Input('a')) => 3,
+                               (1, Input('a')) => 4, (3, Input('a')) => 0, (5, Input('a')) => 0);
+        let dfa = DFA::new(0, set!(4), transitions);
+        let order = dfa.bfs_order();
+        assert_eq!(order, vec![0, 2, 1, 3, 4]);
+        for _ in 0..10 {
+            assert_eq!(dfa.clone().bfs_order(), order);
+        }
+    }
+
+    #[test]
+    fn test_is_explicit() {
+        let dfa = DFA::new(0, set!(1), map!((0, Input('a')) => 1, (1, Input('b')) => 0));
+        assert!(dfa.is_explicit());
+        let dfa = DFA::new(0, set!(1), map!((0, Input('a')) => 1, (1, Anything) => 0));
+        assert!(!dfa.is_explicit());
+    }
+
+    #[test]
+    fn test_residual() {
+        let transitions = map!((0, Input('a')) => 1, (1, Input('a')) => 2, (2, Input('b')) => 3,
+                               (1, Input('c')) => 4);
+        let dfa = DFA::new(0, set!(3, 4), transitions);
+        let state = *dfa.run_states("aa".chars().collect()).last().unwrap();
+        let residual = dfa.residual(&state);
+        assert!(residual.accepts(&['b']));
+        assert!(!residual.accepts(&['a', 'a', 'b']));
+        assert_eq!(residual.num_states(), 2);
+    }
+
+    #[test]
+    fn test_same_structure_and_equivalent() {
+        let dfa1 = DFA::new(0, set!(1), map!((0, Input('a')) => 1));
+        let dfa2 = DFA::new(5, set!(6), map!((5, Input('a')) => 6, (7, Input('b')) => 5));
+        assert!(!dfa1.same_structure(&dfa2));
+        assert!(dfa1.equivalent(&dfa2));
+
+        let dfa3 = DFA::new(0, set!(0, 1), map!((0, Input('a')) => 1));
+        assert!(!dfa1.same_structure(&dfa3));
+        assert!(!dfa1.equivalent(&dfa3));
+
+        assert!(dfa1.same_structure(&dfa1.clone()));
+        assert!(dfa1.equivalent(&dfa1.clone()));
+    }
+
+    #[test]
+    fn test_shortest_match() {
+        let dfa = DFA::new(0, set!(1, 2), map!((0, Input('a')) => 1, (1, Input('a')) => 2));
+        assert_eq!(dfa.shortest_match(&['a', 'a']), Some(1));
+        assert_eq!(dfa.longest_match(&['a', 'a']), Some(2));
+        assert_eq!(dfa.shortest_match(&['a', 'a', 'a']), Some(1));
+        assert_eq!(dfa.longest_match(&['a', 'a', 'a']), Some(2));
+        assert_eq!(dfa.shortest_match(&['b', 'a']), None);
+        assert_eq!(dfa.longest_match(&[]), None);
+    }
+
+    #[test]
+    fn test_iter_zero_sized_input() {
+        let dfa = DFA::new(0, set!(1), map!((0, Input(())) => 1));
+        let mut it = dfa.iter(vec![(); 2]);
+        assert_eq!(it.size_hint(), (1, Some(3)));
+        assert_eq!(it.next(), Some(&0));
+        assert_eq!(it.next(), Some(&1));
+        assert_eq!(it.next(), None);
+        assert_eq!(it.size_hint(), (0, Some(0)));
+
+        // One more state than symbols doesn't fit in a usize
+        let dfa = DFA::new(0, set!(0), map!((0, Input(())) => 0));
+        let mut it = dfa.iter(vec![(); ::std::usize::MAX]);
+        assert_eq!(it.size_hint(), (1, None));
+        assert_eq!(it.next(), Some(&0));
+        assert_eq!(it.size_hint(), (1, Some(::std::usize::MAX)));
+        assert_eq!(it.next(), Some(&0));
+        assert_eq!(it.next(), Some(&0));
+        assert_eq!(it.size_hint(), (1, Some(::std::usize::MAX - 2)));
+    }
+
+    #[test]
+    fn test_word_counts() {
+        // Words over {a, b} that end in b
+        let transitions = map!((0, Input('a')) => 0, (0, Input('b')) => 1,
+                               (1, Input('a')) => 0, (1, Input('b')) => 1);
+        let dfa = DFA::new(0, set!(1), transitions);
+        assert_eq!(dfa.word_counts(4), Ok(vec![0, 1, 2, 4, 8]));
+        assert_eq!(dfa.count_words(3), Ok(4));
+
+        let dfa = DFA::new(0, set!(0, 2), map!((0, Input('a')) => 1, (1, Input('a')) => 2));
+        assert_eq!(dfa.word_counts(3), Ok(vec![1, 0, 1, 0]));
+
+        let dfa = DFA::new(0, set!(1), map!((0, Range('a', 'c')) => 1));
+        assert_eq!(dfa.word_counts(1), Err(AutomatonError::NotExplicit));
+        assert_eq!(dfa.count_words(1), Err(AutomatonError::NotExplicit));
+
+        // A `Range` edge that can't be reached from the start is ignored
+        let dfa = DFA::new(0, set!(1), map!((0, Input('a')) => 1, (2, Range('a', 'c')) => 1));
+        assert_eq!(dfa.word_counts(2), Ok(vec![0, 1, 0]));
+    }
+
+    #[test]
+    fn test_is_viable_prefix() {
+        let transitions = map!((0, Input('a')) => 1, (1, Input('a')) => 2, (2, Input('b')) => 3,
+                               (3, Anything) => 4);
+        let dfa = DFA::new(0, set!(3), transitions);
+        assert!(dfa.is_viable_prefix(&[]));
+        assert!(dfa.is_viable_prefix(&['a', 'a']));
+        assert!(dfa.is_viable_prefix(&['a', 'a', 'b']));
+        assert!(!dfa.is_viable_prefix(&['a', 'a', 'b', 'a']));
+        assert!(!dfa.is_viable_prefix(&['b']));
+    }
+
+    #[test]
+    fn test_validator() {
+        let transitions = map!((0, Input('a')) => 1, (1, Input('a')) => 2, (2, Input('b')) => 3,
+                               (1, Input('c')) => 4);
+        let dfa = DFA::new(0, set!(3), transitions);
+
+        let mut validator = dfa.validator();
+        assert_eq!(validator.status(), PushResult::Viable);
+        let results: Vec<_> = "aabb".chars().map(|c| validator.push(c)).collect();
+        assert_eq!(results, vec![PushResult::Viable, PushResult::Viable, PushResult::Accepted, PushResult::Dead]);
+        assert_eq!(validator.push('a'), PushResult::Dead);
+
+        let mut validator = dfa.validator();
+        assert_eq!(validator.push('a'), PushResult::Viable);
+        assert_eq!(validator.push('c'), PushResult::Dead);
+    }
+
+    #[test]
+    fn test_min_total_accept_length() {
+        // {"", "a", "ab", "b"}
+        let transitions = map!((0, Input('a')) => 1, (1, Input('b')) => 2, (0, Input('b')) => 2,
+                               (2, Input('c')) => 3);
+        let dfa = DFA::new(0, set!(0, 1, 2), transitions);
+        assert_eq!(dfa.min_total_accept_length(), Ok(Some(4)));
+
+        let dfa = DFA::new(0, set!(1), map!((0, Input('a')) => 1, (1, Input('a')) => 1));
+        assert_eq!(dfa.min_total_accept_length(), Ok(None));
+
+        let dfa: DFA<_, char> = DFA::new(0, set!(1), map!((0, Anything) => 1));
+        assert_eq!(dfa.min_total_accept_length(), Err(AutomatonError::NotExplicit));
+
+        // {"a", "b", "c"} is finite, but its words aren't on edges of their own
+        let dfa = DFA::new(0, set!(1), map!((0, Range('a', 'c')) => 1));
+        assert_eq!(dfa.min_total_accept_length(), Err(AutomatonError::NotExplicit));
+
+        // A `Range` edge into a state that can't accept doesn't matter
+        let dfa = DFA::new(0, set!(1), map!((0, Input('a')) => 1, (1, Range('a', 'c')) => 2));
+        assert_eq!(dfa.min_total_accept_length(), Ok(Some(1)));
+    }
+
+    #[test]
+    fn test_with_sync_recovery() {
+        // "ab;" with ';' as the synchronizing token
+        let transitions = map!((0, Input('a')) => 1, (1, Input('b')) => 2, (2, Input(';')) => 3);
+        let dfa = DFA::new(0, set!(3), transitions);
+        let mut sync = HashSet::new();
+        sync.insert(';');
+        let recovering = dfa.with_sync_recovery(&sync, 5).unwrap();
+
+        let bad: Vec<char> = "ax;ab;".chars().collect();
+        assert!(!dfa.accepts(&bad));
+        assert!(recovering.accepts(&bad));
+        assert!(recovering.accepts(&"ab;".chars().collect::<Vec<_>>()));
+        assert!(recovering.accepts(&"xx;ab;".chars().collect::<Vec<_>>()));
+        assert!(!recovering.accepts(&"ax;a".chars().collect::<Vec<_>>()));
+        assert!(!recovering.accepts(&"abx".chars().collect::<Vec<_>>()));
+
+        // The ';' that breaks "a;" is also the one to recover at
+        assert!(recovering.accepts(&"a;ab;".chars().collect::<Vec<_>>()));
+        assert!(recovering.accepts(&";ab;".chars().collect::<Vec<_>>()));
+
+        // An explicit trap state recovers the same way
+        let recovering = dfa.complete_with(4).unwrap().with_sync_recovery(&sync, 5).unwrap();
+        assert!(recovering.accepts(&bad));
+        assert!(recovering.accepts(&"a;ab;".chars().collect::<Vec<_>>()));
+        assert!(!recovering.accepts(&"ax;a".chars().collect::<Vec<_>>()));
+
+        assert_eq!(dfa.with_sync_recovery(&sync, 3).err(), Some(AutomatonError::TrapStateExists));
+    }
 }
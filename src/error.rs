@@ -0,0 +1,29 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum AutomatonError {
+    StartOutOfRange(usize),
+    EdgeOutOfRange(usize),
+    AcceptOutOfRange(usize),
+    TrapStateExists,
+    DuplicatePrefix(String),
+    DeterminizeBlowup(usize),
+    NotExplicit
+}
+
+impl fmt::Display for AutomatonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AutomatonError::StartOutOfRange(i) => write!(f, "start state index {} is out of range", i),
+            AutomatonError::EdgeOutOfRange(i) => write!(f, "edge {} refers to a state out of range", i),
+            AutomatonError::AcceptOutOfRange(i) => write!(f, "accept state index {} is out of range", i),
+            AutomatonError::TrapStateExists => write!(f, "trap state is already a state of the machine"),
+            AutomatonError::DuplicatePrefix(ref p) => write!(f, "prefix {:?} is listed more than once", p),
+            AutomatonError::DeterminizeBlowup(n) => write!(f, "determinization exceeded the bound at {} states", n),
+            AutomatonError::NotExplicit => write!(f, "machine has edges that aren't labelled with a single input")
+        }
+    }
+}
+
+impl Error for AutomatonError {}
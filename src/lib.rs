@@ -4,9 +4,11 @@ use std::fmt::Display;
 
 pub mod dfa;
 pub mod nfa;
+pub mod error;
 
-pub use nfa::{NFA, Transition};
+pub use nfa::{NFA, Transition, Symbol};
 pub use dfa::DFA;
+pub use error::AutomatonError;
 
 pub trait Automaton {
     type State;
@@ -1,11 +1,13 @@
-use {Automaton, DFA};
+use {Automaton, AutomatonError, DFA};
 use std::fmt::Display;
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::collections::hash_map::Entry::Vacant;
+use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::collections::{HashSet, HashMap, VecDeque, BTreeSet};
 use std::hash::Hash;
-use nfa::Transition::{Input, Epsilon, Anything};
+use std::cmp;
+use std::sync::OnceLock;
+use nfa::Transition::{Input, Epsilon, Anything, Range};
 
 #[macro_export]
 macro_rules! set {
@@ -20,53 +22,178 @@ macro_rules! set {
 pub struct NFA<S: Eq + Hash = usize, I: Eq + Hash = char> {
     start: S,
     accept_states: HashSet<S>,
-    transitions: HashMap<(S, Transition<I>), HashSet<S>>
+    transitions: HashMap<(S, Transition<I>), HashSet<S>>,
+    // Filled by the first `shift_and` and cleared whenever the machine changes
+    bit_parallel: OnceLock<Option<BitParallelNfa>>
 }
 
-#[derive(Debug, Hash, Eq, PartialEq, Copy, Clone)]
+#[derive(Debug, Hash, Eq, PartialEq, Ord, PartialOrd, Copy, Clone)]
 pub enum Transition<I> {
     Anything,
     Epsilon,
-    Input(I)
+    Input(I),
+    Range(I, I)
+}
+
+impl<I: PartialOrd> Transition<I> {
+    pub fn matches(&self, c: &I) -> bool {
+        match *self {
+            Anything => true,
+            Epsilon => false,
+            Input(ref i) => i == c,
+            Range(ref lo, ref hi) => lo <= c && c <= hi
+        }
+    }
+}
+
+// Alphabets whose `Range` labels can be split into disjoint intervals. Each symbol needs to know
+// its neighbours so that an interval can be closed off just before or after another label.
+pub trait Symbol: Ord + Clone {
+    fn min_symbol() -> Self;
+    fn max_symbol() -> Self;
+    fn succ(&self) -> Option<Self>;
+    fn pred(&self) -> Option<Self>;
+}
+
+impl Symbol for char {
+    fn min_symbol() -> char {
+        '\0'
+    }
+
+    fn max_symbol() -> char {
+        ::std::char::MAX
+    }
+
+    // Steps over the surrogate range, which isn't made of valid chars
+    fn succ(&self) -> Option<char> {
+        match *self {
+            '\u{D7FF}' => Some('\u{E000}'),
+            c => ::std::char::from_u32(c as u32 + 1)
+        }
+    }
+
+    fn pred(&self) -> Option<char> {
+        match *self {
+            '\0' => None,
+            '\u{E000}' => Some('\u{D7FF}'),
+            c => ::std::char::from_u32(c as u32 - 1)
+        }
+    }
+}
+
+macro_rules! int_symbol {
+    ($($t:ty),*) => ($(
+        impl Symbol for $t {
+            fn min_symbol() -> $t {
+                <$t>::min_value()
+            }
+
+            fn max_symbol() -> $t {
+                <$t>::max_value()
+            }
+
+            fn succ(&self) -> Option<$t> {
+                self.checked_add(1)
+            }
+
+            fn pred(&self) -> Option<$t> {
+                self.checked_sub(1)
+            }
+        }
+    )*)
+}
+
+int_symbol!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+// Splits the whole alphabet into the coarsest sorted intervals that each of `labels` either
+// contains entirely or not at all, so one symbol stands for its whole interval.
+pub(crate) fn alphabet_intervals<'a, I: Symbol + 'a, T>(labels: T) -> Vec<(I, I)>
+        where T: IntoIterator<Item = &'a Transition<I>> {
+    let mut starts = vec![I::min_symbol()];
+    for trans in labels {
+        let (lo, hi) = match *trans {
+            Input(ref c) => (c, c),
+            Range(ref lo, ref hi) => (lo, hi),
+            _ => continue
+        };
+        starts.push(lo.clone());
+        starts.extend(hi.succ());
+    }
+    starts.sort();
+    starts.dedup();
+
+    let mut intervals = Vec::new();
+    for i in 0..starts.len() {
+        let hi = match starts.get(i + 1) {
+            Some(next) => next.pred().unwrap(),
+            None => I::max_symbol()
+        };
+        intervals.push((starts[i].clone(), hi));
+    }
+    intervals
 }
 
 pub struct NFAIter<'a, S: 'a, I: 'a> {
     queue: VecDeque<(&'a S, usize)>,
+    seen: HashSet<(&'a S, usize)>,
     input: Vec<I>,
-    transitions: &'a HashMap<(S, Transition<I>), HashSet<S>>
+    transitions: &'a HashMap<(S, Transition<I>), HashSet<S>>,
+    ranges: RangeEdges<'a, S, I>
+}
+
+type RangeEdges<'a, S, I> = HashMap<&'a S, Vec<(&'a Transition<I>, &'a HashSet<S>)>>;
+
+fn range_edges<'a, S: Eq + Hash, I: Eq + Hash>(transitions: &'a HashMap<(S, Transition<I>), HashSet<S>>)
+                                               -> RangeEdges<'a, S, I> {
+    let mut ranges = HashMap::new();
+    for (&(ref from, ref trans), to) in transitions.iter() {
+        if let Range(..) = *trans {
+            ranges.entry(from).or_insert(Vec::new()).push((trans, to));
+        }
+    }
+    ranges
 }
 
-impl<'a, S: 'a + Hash + Eq + Copy, I: Hash + Eq + Copy> Iterator for NFAIter<'a, S, I> {
+// Every target set reached from `state` by an edge that matches `c`
+fn symbol_targets<'a, S, I>(transitions: &'a HashMap<(S, Transition<I>), HashSet<S>>,
+                            ranges: &RangeEdges<'a, S, I>, state: &S, c: &I) -> Vec<&'a HashSet<S>>
+        where S: Eq + Hash + Clone, I: Eq + Hash + Clone + PartialOrd {
+    let mut targets = Vec::new();
+    targets.extend(transitions.get(&(state.clone(), Input(c.clone()))));
+    targets.extend(transitions.get(&(state.clone(), Anything)));
+    if let Some(edges) = ranges.get(state) {
+        targets.extend(edges.iter().filter(|&&(trans, _)| trans.matches(c)).map(|&(_, to)| to));
+    }
+    targets
+}
+
+impl<'a, S: 'a + Hash + Eq + Copy, I: Hash + Eq + Copy + PartialOrd> Iterator for NFAIter<'a, S, I> {
     type Item = &'a S;
 
     fn next(&mut self) -> Option<&'a S> {
-        if self.queue.is_empty() {
-            None
-        } else {
-            let (state, pos) = match self.queue.pop_front() {
-                Some(s) => s,
-                None => panic!("Shouldn't happen")
-            };
-
-            if pos < self.input.len() {
-                if let Some(set) = self.transitions.get(&(*state, Input(self.input[pos]))) {
-                    for item in set {
-                        self.queue.push_back((item, pos + 1))
-                    }
-                } else if let Some(set) = self.transitions.get(&(*state, Anything)) {
-                    for item in set {
+        let (state, pos) = match self.queue.pop_front() {
+            Some(s) => s,
+            None => return None
+        };
+
+        if pos < self.input.len() {
+            for set in symbol_targets(self.transitions, &self.ranges, state, &self.input[pos]) {
+                for item in set {
+                    if self.seen.insert((item, pos + 1)) {
                         self.queue.push_back((item, pos + 1))
                     }
                 }
             }
-            if let Some(set) = self.transitions.get(&(*state, Epsilon)) {
-                for item in set {
+        }
+        if let Some(set) = self.transitions.get(&(*state, Epsilon)) {
+            for item in set {
+                if self.seen.insert((item, pos)) {
                     self.queue.push_back((item, pos))
                 }
             }
-
-            Some(state)
         }
+
+        Some(state)
     }
 
     fn size_hint(&self) -> (usize, Option<usize>){
@@ -77,7 +204,8 @@ impl<'a, S: 'a + Hash + Eq + Copy, I: Hash + Eq + Copy> Iterator for NFAIter<'a,
 impl<S: Clone + Eq + Hash = usize, I: Eq + Hash + Copy = char> NFA<S, I> {
     pub fn new(start: S, accept_states: HashSet<S>,
                transitions: HashMap<(S, Transition<I>), HashSet<S>>) -> NFA<S, I> {
-        NFA { start: start, accept_states: accept_states, transitions: transitions }
+        NFA { start: start, accept_states: accept_states, transitions: transitions,
+              bit_parallel: OnceLock::new() }
     }
 
     pub fn get_accept_states(&self) -> &HashSet<S> {
@@ -92,23 +220,212 @@ impl<S: Clone + Eq + Hash = usize, I: Eq + Hash + Copy = char> NFA<S, I> {
         &self.transitions
     }
 
+    pub fn is_explicit(&self) -> bool {
+        self.transitions.keys().all(|&(_, trans)| match trans { Input(_) => true, _ => false })
+    }
+
+    pub fn accept_states_sorted(&self) -> Vec<S> where S: Ord {
+        let mut accept_states: Vec<S> = self.accept_states.iter().cloned().collect();
+        accept_states.sort();
+        accept_states
+    }
+
+    pub fn num_states(&self) -> usize {
+        let mut states = HashSet::new();
+        states.insert(&self.start);
+        states.extend(self.accept_states.iter());
+        for (&(ref from, _), to) in self.transitions.iter() {
+            states.insert(from);
+            states.extend(to.iter());
+        }
+        states.len()
+    }
+
+    pub fn determinized_state_count(&self) -> usize where S: Ord {
+        self.into_dfa().num_states()
+    }
+
+    pub fn determinization_ratio(&self) -> f64 where S: Ord {
+        self.determinized_state_count() as f64 / self.num_states() as f64
+    }
+
+    pub fn epsilon_transitions(&self) -> Vec<(S, S)> {
+        let mut epsilons = Vec::new();
+        for (&(ref from, trans), to) in self.transitions.iter() {
+            if trans == Epsilon {
+                for s in to {
+                    epsilons.push((from.clone(), s.clone()));
+                }
+            }
+        }
+        epsilons
+    }
+
+    pub fn epsilon_count(&self) -> usize {
+        self.transitions.iter().filter(|&(k, _)| k.1 == Epsilon).map(|(_, to)| to.len()).sum()
+    }
+
+    pub fn from_adjacency(nodes: Vec<S>, edges: Vec<(usize, Transition<I>, usize)>, start: usize,
+                          accept: Vec<usize>) -> Result<NFA<S, I>, AutomatonError> {
+        if start >= nodes.len() {
+            return Err(AutomatonError::StartOutOfRange(start))
+        }
+
+        let mut accept_states = HashSet::new();
+        for i in accept {
+            if i >= nodes.len() {
+                return Err(AutomatonError::AcceptOutOfRange(i))
+            }
+            accept_states.insert(nodes[i].clone());
+        }
+
+        let mut transitions = HashMap::new();
+        for (i, (from, trans, to)) in edges.into_iter().enumerate() {
+            if from >= nodes.len() || to >= nodes.len() {
+                return Err(AutomatonError::EdgeOutOfRange(i))
+            }
+            transitions.entry((nodes[from].clone(), trans)).or_insert(HashSet::new()).insert(nodes[to].clone());
+        }
+
+        Ok(NFA::new(nodes[start].clone(), accept_states, transitions))
+    }
+
+    pub fn to_adjacency(&self) -> (Vec<S>, Vec<(usize, Transition<I>, usize)>, usize, Vec<usize>) where S: Ord {
+        let mut nodes = BTreeSet::new();
+        nodes.insert(self.start.clone());
+        nodes.extend(self.accept_states.iter().cloned());
+        for (&(ref from, _), to) in self.transitions.iter() {
+            nodes.insert(from.clone());
+            nodes.extend(to.iter().cloned());
+        }
+
+        let nodes: Vec<S> = nodes.into_iter().collect();
+        let (edges, start, accept) = {
+            let index = |s: &S| nodes.binary_search(s).unwrap();
+
+            let mut edges = Vec::new();
+            for (&(ref from, trans), to) in self.transitions.iter() {
+                for s in to {
+                    edges.push((index(from), trans, index(s)));
+                }
+            }
+
+            let mut accept: Vec<usize> = self.accept_states.iter().map(|s| index(s)).collect();
+            accept.sort();
+            (edges, index(&self.start), accept)
+        };
+
+        (nodes, edges, start, accept)
+    }
+
     pub fn iter(&self, input: Vec<I>) -> NFAIter<S, I> {
         let mut queue = VecDeque::new();
+        let mut seen = HashSet::new();
         queue.push_back((&self.start, 0));
-        NFAIter { queue: queue, input: input, transitions: &self.transitions }
+        seen.insert((&self.start, 0));
+        NFAIter { queue: queue, seen: seen, input: input, transitions: &self.transitions,
+                  ranges: range_edges(&self.transitions) }
     }
 
+    // Each label is followed on its own, together with the `Anything` edges, which match its
+    // symbols as well. A `Range` is taken as a label of its own, which is exact unless it overlaps
+    // another label; `into_dfa_by_intervals` splits overlapping labels apart.
     pub fn into_dfa(&self) -> DFA<usize, I> where S: Ord {
-        let clone = self.clone();
-        let mut alphabet = HashSet::new();
-        for (trans, _) in clone.transitions.iter() {
-            // Don't add epsilon
-            match trans.1 {
-                Input(c) => alphabet.insert(Input(c)),
-                Anything => alphabet.insert(Anything),
-                _ => {false},
-            };
+        match self.determinize_labels(None) {
+            Ok(dfa) => dfa,
+            Err(_) => unreachable!()
         }
+    }
+
+    // The subsets are followed over intervals of the alphabet that every label either covers or
+    // misses, so `Range` and `Anything` edges are merged with the explicit ones they overlap.
+    pub fn into_dfa_by_intervals(&self) -> DFA<usize, I> where S: Ord, I: Symbol {
+        let ranges = range_edges(&self.transitions);
+        let alphabet: Vec<Transition<I>> = alphabet_intervals(self.transitions.keys().map(|k| &k.1))
+            .into_iter().map(|(lo, hi)| if lo == hi { Input(lo) } else { Range(lo, hi) }).collect();
+        let targets = |s: &S, label: &Transition<I>| match *label {
+            Input(c) | Range(c, _) => symbol_targets(&self.transitions, &ranges, s, &c),
+            _ => Vec::new()
+        };
+        match self.determinize(&alphabet, targets, None) {
+            Ok(dfa) => dfa,
+            Err(_) => unreachable!()
+        }
+    }
+
+    pub fn into_minimal_dfa(&self) -> DFA<usize, I> where S: Ord, I: Ord {
+        self.into_dfa().canonical_minimal()
+    }
+
+    pub fn try_into_dfa_bounded(&self, max_states: usize) -> Result<DFA<usize, I>, AutomatonError>
+            where S: Ord {
+        self.determinize_labels(Some(max_states))
+    }
+
+    // Keeps only the states whose outgoing edges are already deterministic: no epsilon, a single
+    // target per edge and no two edges matching the same symbol. The DFA's language is a subset
+    // of the NFA's, since any run through the fragment is also a run of the NFA.
+    pub fn deterministic_fragment(&self) -> DFA<S, I> where I: PartialOrd {
+        let overlaps = |a: &Transition<I>, b: &Transition<I>| match (*a, *b) {
+            (Anything, _) | (_, Anything) => true,
+            (Input(c), t) | (t, Input(c)) => t.matches(&c),
+            (Range(lo, hi), Range(lo2, hi2)) => lo <= hi2 && lo2 <= hi,
+            _ => false
+        };
+
+        // An edge without targets can never be taken, so it is left out
+        let mut outgoing: HashMap<&S, Vec<(&Transition<I>, &HashSet<S>)>> = HashMap::new();
+        for (&(ref from, ref trans), to) in self.transitions.iter().filter(|&(_, to)| !to.is_empty()) {
+            outgoing.entry(from).or_insert(Vec::new()).push((trans, to));
+        }
+
+        let deterministic: HashSet<&S> = outgoing.iter().filter(|&(_, edges)| {
+            edges.iter().enumerate().all(|(i, &(trans, to))| {
+                *trans != Epsilon && to.len() == 1 && edges[i + 1..].iter().all(|&(other, _)| !overlaps(trans, other))
+            })
+        }).map(|(s, _)| *s).collect();
+        let keep = |s: &S| deterministic.contains(s) || !outgoing.contains_key(s);
+
+        let mut transitions = HashMap::new();
+        for (&(ref from, trans), to) in self.transitions.iter() {
+            if !deterministic.contains(from) {
+                continue;
+            }
+            if let Some(to) = to.iter().next() {
+                if keep(to) {
+                    transitions.insert((from.clone(), trans), to.clone());
+                }
+            }
+        }
+        let accept_states = self.accept_states.iter().filter(|s| keep(s)).cloned().collect();
+        DFA::new(self.start.clone(), accept_states, transitions)
+    }
+
+    fn determinize_labels(&self, max_states: Option<usize>) -> Result<DFA<usize, I>, AutomatonError>
+            where S: Ord {
+        let mut alphabet = Vec::new();
+        for &(_, trans) in self.transitions.keys() {
+            if trans != Epsilon && !alphabet.contains(&trans) {
+                alphabet.push(trans);
+            }
+        }
+        let targets = |s: &S, label: &Transition<I>| {
+            let mut targets = Vec::new();
+            targets.extend(self.transitions.get(&(s.clone(), *label)));
+            if *label != Anything {
+                targets.extend(self.transitions.get(&(s.clone(), Anything)));
+            }
+            targets
+        };
+        self.determinize(&alphabet, targets, max_states)
+    }
+
+    // Subset construction over `alphabet`, where `targets` gives the target sets that a state
+    // reaches on the symbols of a label
+    fn determinize<'a, F>(&'a self, alphabet: &[Transition<I>], targets: F, max_states: Option<usize>)
+                          -> Result<DFA<usize, I>, AutomatonError>
+            where S: Ord, F: Fn(&S, &Transition<I>) -> Vec<&'a HashSet<S>> {
+        let exceeds = |count: usize| max_states.map_or(false, |max| count > max);
 
         let mut states = HashMap::new();
         let mut accept_states = HashSet::new();
@@ -117,33 +434,48 @@ impl<S: Clone + Eq + Hash = usize, I: Eq + Hash + Copy = char> NFA<S, I> {
         let mut get_id = || { let ret = id; id += 1; ret };
         let mut queue = VecDeque::new();
 
-        let mut init_state = set!(clone.start.clone());
-        clone.epsilon_closure(&mut init_state);
+        let mut init_state = set!(self.start.clone());
+        self.epsilon_closure(&mut init_state);
+        if exceeds(1) {
+            return Err(AutomatonError::DeterminizeBlowup(1))
+        }
+        if self.get_accept(&init_state).is_some() {
+            accept_states.insert(0);
+        }
         queue.push_back((get_id(), init_state.clone()));
         states.insert(init_state.into_iter().collect(), 0);
         while let Some((cur_id, cur_state)) = queue.pop_front() {
-            for a in alphabet.iter() {
-                let mut new_state = clone.reachable_states(&cur_state, *a);
-                clone.epsilon_closure(&mut new_state);
+            for label in alphabet.iter() {
+                let mut new_state = HashSet::new();
+                for s in cur_state.iter() {
+                    for set in targets(s, label) {
+                        new_state.extend(set.iter().cloned());
+                    }
+                }
+                self.epsilon_closure(&mut new_state);
 
                 let new_state_set: BTreeSet<_> = new_state.clone().into_iter().collect();
                 if new_state.len() > 0 {
-                    if let Vacant(entry) = states.entry(new_state_set.clone()) {
-                        let id = get_id();
-                        if let Some(s) = clone.get_accept(&new_state) {
-                            accept_states.insert(id);
+                    let id = match states.entry(new_state_set) {
+                        Vacant(entry) => {
+                            let id = get_id();
+                            if exceeds(id + 1) {
+                                return Err(AutomatonError::DeterminizeBlowup(id + 1))
+                            }
+                            if self.get_accept(&new_state).is_some() {
+                                accept_states.insert(id);
+                            }
+                            queue.push_back((id, new_state));
+                            *entry.insert(id)
                         }
-                        queue.push_back((id, new_state));
-                        entry.insert(id);
-                    }
-                    // TODO: Find a way to not requery
-                    let id = states.get(&new_state_set).unwrap();
-                    transitions.insert((cur_id, *a), *id);
+                        Occupied(entry) => *entry.get()
+                    };
+                    transitions.insert((cur_id, *label), id);
                 }
             }
         }
 
-        DFA::new(0, accept_states, transitions)
+        Ok(DFA::new(0, accept_states, transitions))
     }
 
     fn get_accept(&self, states: &HashSet<S>) -> Option<S> {
@@ -185,35 +517,195 @@ impl<S: Clone + Eq + Hash = usize, I: Eq + Hash + Copy = char> NFA<S, I> {
     }
 }
 
-impl<S, I> Automaton for NFA<S, I> where S: Hash + Eq + Copy, I: Hash + Eq + Copy {
+impl<I: Eq + Hash + Copy> NFA<usize, I> {
+    fn shifted(&self, offset: usize) -> NFA<usize, I> {
+        let accept_states = self.accept_states.iter().map(|s| s + offset).collect();
+        let transitions = self.transitions.iter()
+            .map(|(&(from, trans), to)| ((from + offset, trans), to.iter().map(|s| s + offset).collect()))
+            .collect();
+        NFA::new(self.start + offset, accept_states, transitions)
+    }
+
+    fn max_state(&self) -> usize {
+        let mut max = self.start;
+        for s in self.accept_states.iter() {
+            max = cmp::max(max, *s);
+        }
+        for (&(from, _), to) in self.transitions.iter() {
+            max = cmp::max(max, from);
+            for s in to {
+                max = cmp::max(max, *s);
+            }
+        }
+        max
+    }
+
+    // Relabeling `other` apart from `self` needs fresh state labels, hence the `usize` states
+    pub fn union_assign(&mut self, other: &NFA<usize, I>) {
+        let other = other.shifted(self.max_state() + 1);
+        let start = other.max_state() + 1;
+
+        let mut starts = HashSet::new();
+        starts.insert(self.start);
+        starts.insert(other.start);
+        self.transitions.extend(other.transitions.into_iter());
+        self.transitions.insert((start, Epsilon), starts);
+        self.accept_states.extend(other.accept_states.into_iter());
+        self.start = start;
+        self.bit_parallel = OnceLock::new();
+    }
+
+    pub fn join(parts: &[&NFA<usize, I>], sep: &NFA<usize, I>) -> NFA<usize, I> {
+        let mut pieces = Vec::new();
+        for (i, part) in parts.iter().enumerate() {
+            if i > 0 {
+                pieces.push(sep);
+            }
+            pieces.push(*part);
+        }
+
+        let mut joined = NFA::new(0, set!(0), HashMap::new());
+        for piece in pieces {
+            let piece = piece.shifted(joined.max_state() + 1);
+            for s in joined.accept_states.iter() {
+                joined.transitions.entry((*s, Epsilon)).or_insert(HashSet::new()).insert(piece.start);
+            }
+            joined.transitions.extend(piece.transitions.into_iter());
+            joined.accept_states = piece.accept_states;
+        }
+        joined
+    }
+}
+
+impl NFA<usize, char> {
+    // Returns `None` for machines with more than 64 states, whose state set doesn't fit in a `u64`
+    pub fn compile_bit_parallel(&self) -> Option<BitParallelNfa> {
+        let (nodes, edges, start, accept) = self.to_adjacency();
+        if nodes.len() > 64 {
+            return None
+        }
+
+        let mut closure: Vec<u64> = (0..nodes.len()).map(|i| 1 << i).collect();
+        loop {
+            let mut changed = false;
+            for &(from, trans, to) in edges.iter() {
+                if trans == Epsilon && closure[from] | closure[to] != closure[from] {
+                    closure[from] |= closure[to];
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        // Each byte of the state mask indexes a table of the successors of that subset of eight
+        // states, so a step costs one lookup per byte however many states are active
+        let intervals = alphabet_intervals(self.transitions.keys().map(|k| &k.1));
+        let chunks = (nodes.len() + 7) / 8;
+        let mut tables = Vec::new();
+        for &(lo, _) in intervals.iter() {
+            let mut succ = vec![0u64; nodes.len()];
+            for &(from, trans, to) in edges.iter() {
+                if trans.matches(&lo) {
+                    succ[from] |= closure[to];
+                }
+            }
+
+            let mut table = vec![[0u64; 256]; chunks];
+            for (chunk, t) in table.iter_mut().enumerate() {
+                for byte in 1..256usize {
+                    let state = chunk * 8 + byte.trailing_zeros() as usize;
+                    t[byte] = t[byte & (byte - 1)] | succ.get(state).cloned().unwrap_or(0);
+                }
+            }
+            tables.push(table);
+        }
+
+        Some(BitParallelNfa {
+            start: closure[start],
+            accept: accept.iter().fold(0, |mask, &i| mask | 1 << i),
+            intervals: intervals,
+            tables: tables
+        })
+    }
+
+    // The tables are compiled by the first call and reused by later ones. Machines with more than
+    // 64 states fall back to `run`.
+    pub fn shift_and(&self, input: &[char]) -> bool {
+        match *self.bit_parallel.get_or_init(|| self.compile_bit_parallel()) {
+            Some(ref matcher) => matcher.accepts(input),
+            None => self.run(input.to_vec()).is_some()
+        }
+    }
+}
+
+// A table-driven subset simulation of an NFA of at most 64 states: the set of active states is a
+// `u64` mask, and each step looks the successors of the mask up in per-byte tables.
+#[derive(Debug, Clone)]
+pub struct BitParallelNfa {
+    start: u64,
+    accept: u64,
+    intervals: Vec<(char, char)>,
+    tables: Vec<Vec<[u64; 256]>>
+}
+
+impl BitParallelNfa {
+    pub fn accepts(&self, input: &[char]) -> bool {
+        let mut active = self.start;
+        for c in input {
+            // The intervals cover the whole alphabet, so the search always succeeds
+            let i = self.intervals.binary_search_by(|&(lo, hi)| {
+                if hi < *c {
+                    cmp::Ordering::Less
+                } else if lo > *c {
+                    cmp::Ordering::Greater
+                } else {
+                    cmp::Ordering::Equal
+                }
+            }).unwrap();
+
+            let mut next = 0;
+            for (chunk, table) in self.tables[i].iter().enumerate() {
+                next |= table[(active >> (chunk * 8)) as usize & 0xFF];
+            }
+            active = next;
+            if active == 0 {
+                return false
+            }
+        }
+        active & self.accept != 0
+    }
+}
+
+impl<S, I> Automaton for NFA<S, I> where S: Hash + Eq + Copy, I: Hash + Eq + Copy + PartialOrd {
     type State = S;
     type Alphabet = I;
 
     fn run(&self, s: Vec<I>) -> Option<Vec<I>> {
+        let ranges = range_edges(&self.transitions);
         let mut queue = VecDeque::new();
-        let mut path = Vec::<I>::new();
+        let mut seen = HashSet::new();
 
         queue.push_back((self.start, 0));
         while let Some((state, pos)) = queue.pop_front() {
-            if let Some(set) = self.transitions.get(&(state, Epsilon)) {
-                for item in set {
-                    queue.push_back((*item, pos))
-                }
+            // Epsilon cycles would otherwise revisit the same configuration forever
+            if !seen.insert((state, pos)) {
+                continue;
             }
 
-            if let Some(set) = self.transitions.get(&(state, Anything)) {
+            if let Some(set) = self.transitions.get(&(state, Epsilon)) {
                 for item in set {
-                    queue.push_back((*item, pos + 1))
+                    queue.push_back((*item, pos))
                 }
             }
 
-            path.push(s[pos]);
             if pos == s.len() {
                 if self.accept_states.contains(&state) {
-                    return Some(path)
+                    return Some(s)
                 }
             } else {
-                if let Some(set) = self.transitions.get(&(state, Input(s[pos]))) {
+                for set in symbol_targets(&self.transitions, &ranges, &state, &s[pos]) {
                     for item in set {
                         queue.push_back((*item, pos + 1))
                     }
@@ -227,7 +719,8 @@ impl<S, I> Automaton for NFA<S, I> where S: Hash + Eq + Copy, I: Hash + Eq + Cop
 #[cfg(test)]
 mod test {
     use {Automaton, NFA};
-    use nfa::Transition::Input;
+    use error::AutomatonError;
+    use nfa::Transition::{Input, Epsilon, Anything, Range};
     use std::collections::HashSet;
 
     macro_rules! set {
@@ -256,8 +749,8 @@ mod test {
         assert_eq!(nfa.run("aaaaa".chars().collect()), None);
         assert_eq!(nfa.run("aabaa".chars().collect()), None);
         assert_eq!(nfa.run("aababbb".chars().collect()), None);
-        assert_eq!(nfa.run("aababb".chars().collect()), Some(2));
-        assert_eq!(nfa.run("aabb".chars().collect()), Some(2));
+        assert_eq!(nfa.run("aababb".chars().collect()), Some("aababb".chars().collect()));
+        assert_eq!(nfa.run("aabb".chars().collect()), Some("aabb".chars().collect()));
     }
 
     #[ignore] // We need to check for isomorphism, not equality
@@ -330,4 +823,244 @@ mod test {
         nfa.epsilon_closure(&mut s);
         assert_eq!(s, set!(2));
     }
+
+    #[test]
+    fn test_adjacency_round_trip() {
+        let transitions = map!((0, Input('a')) => set!(0, 1),
+                               (0, Epsilon) => set!(2),
+                               (1, Input('b')) => set!(2));
+        let nfa = NFA::new(0, set!(2), transitions);
+
+        let (nodes, edges, start, accept) = nfa.to_adjacency();
+        let nfa2 = NFA::from_adjacency(nodes, edges, start, accept).unwrap();
+        assert_eq!(nfa2.get_start_state(), nfa.get_start_state());
+        assert_eq!(nfa2.get_accept_states(), nfa.get_accept_states());
+        assert_eq!(nfa2.get_transitions(), nfa.get_transitions());
+    }
+
+    #[test]
+    fn test_from_adjacency_out_of_range() {
+        let nfa = NFA::<char, char>::from_adjacency(vec!['x', 'y'], vec![(0, Input('a'), 2)], 0, vec![1]);
+        assert_eq!(nfa.err(), Some(AutomatonError::EdgeOutOfRange(0)));
+        let nfa = NFA::<char, char>::from_adjacency(vec!['x', 'y'], vec![], 2, vec![1]);
+        assert_eq!(nfa.err(), Some(AutomatonError::StartOutOfRange(2)));
+        let nfa = NFA::<char, char>::from_adjacency(vec!['x', 'y'], vec![], 0, vec![5]);
+        assert_eq!(nfa.err(), Some(AutomatonError::AcceptOutOfRange(5)));
+    }
+
+    #[test]
+    fn test_epsilon_transitions() {
+        let transitions = map!((0, Epsilon) => set!(1, 2),
+                               (1, Input('a')) => set!(2));
+        let nfa = NFA::new(0, set!(2), transitions);
+        let mut epsilons = nfa.epsilon_transitions();
+        epsilons.sort();
+        assert_eq!(epsilons, vec![(0, 1), (0, 2)]);
+        assert_eq!(nfa.epsilon_count(), 2);
+    }
+
+    #[test]
+    fn test_determinization_ratio() {
+        // (a|b)*a(a|b)(a|b)(a|b) needs a DFA state for every combination of the last four symbols
+        let transitions = map!((0, Input('a')) => set!(0, 1), (0, Input('b')) => set!(0),
+                               (1, Input('a')) => set!(2), (1, Input('b')) => set!(2),
+                               (2, Input('a')) => set!(3), (2, Input('b')) => set!(3),
+                               (3, Input('a')) => set!(4), (3, Input('b')) => set!(4));
+        let nfa = NFA::new(0, set!(4), transitions);
+        assert_eq!(nfa.num_states(), 5);
+        assert_eq!(nfa.determinized_state_count(), 16);
+        assert!(nfa.determinization_ratio() > 1.0);
+    }
+
+    #[test]
+    fn test_accept_states_sorted() {
+        let nfa = NFA::new(0, set!(4, 1, 3), map!((0, Input('a')) => set!(1, 3, 4)));
+        assert_eq!(nfa.accept_states_sorted(), vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn test_join() {
+        let digits = map!((0, Input('1')) => set!(1), (0, Input('2')) => set!(1),
+                          (0, Input('3')) => set!(1), (0, Input('4')) => set!(1),
+                          (1, Input('1')) => set!(1), (1, Input('2')) => set!(1),
+                          (1, Input('3')) => set!(1), (1, Input('4')) => set!(1));
+        let number = NFA::new(0, set!(1), digits);
+        let comma = NFA::new(0, set!(1), map!((0, Input(',')) => set!(1)));
+
+        let list = NFA::join(&[&number, &number], &comma).into_dfa();
+        assert!(list.accepts(&['1', '2', ',', '3', '4']));
+        assert!(!list.accepts(&['1', '2', ',']));
+        assert!(!list.accepts(&['1', '2']));
+
+        let empty = NFA::join(&[], &comma).into_dfa();
+        assert!(empty.accepts(&[]));
+        assert!(!empty.accepts(&[',']));
+    }
+
+    #[test]
+    fn test_is_explicit() {
+        let nfa = NFA::new(0, set!(1), map!((0, Input('a')) => set!(0, 1)));
+        assert!(nfa.is_explicit());
+        let nfa = NFA::new(0, set!(1), map!((0, Input('a')) => set!(0), (0, Epsilon) => set!(1)));
+        assert!(!nfa.is_explicit());
+    }
+
+    #[test]
+    fn test_try_into_dfa_bounded() {
+        let transitions = map!((0, Input('a')) => set!(0, 1), (0, Input('b')) => set!(0),
+                               (1, Input('a')) => set!(2), (1, Input('b')) => set!(2),
+                               (2, Input('a')) => set!(3), (2, Input('b')) => set!(3));
+        let nfa = NFA::new(0, set!(3), transitions);
+        assert_eq!(nfa.try_into_dfa_bounded(4).err(), Some(AutomatonError::DeterminizeBlowup(5)));
+
+        let dfa = nfa.try_into_dfa_bounded(8).unwrap();
+        assert_eq!(dfa.num_states(), 8);
+        assert!(dfa.accepts(&['b', 'a', 'b', 'a']));
+    }
+
+    #[test]
+    fn test_run_end_of_input() {
+        let transitions = map!((0, Input('a')) => set!(1), (1, Anything) => set!(2));
+        let nfa = NFA::new(0, set!(1, 2), transitions);
+        assert_eq!(nfa.run(vec![]), None);
+        assert_eq!(nfa.run(vec!['a']), Some(vec!['a']));
+        assert_eq!(nfa.run(vec!['a', 'z']), Some(vec!['a', 'z']));
+        assert_eq!(nfa.run(vec!['a', 'z', 'z']), None);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let transitions = map!((0, Range('a', 'c')) => set!(1), (0, Input('b')) => set!(2),
+                               (1, Anything) => set!(1), (2, Input('x')) => set!(1));
+        let nfa = NFA::new(0, set!(1), transitions);
+        assert_eq!(nfa.run(vec!['b']), Some(vec!['b']));
+        assert_eq!(nfa.run(vec!['c', 'z']), Some(vec!['c', 'z']));
+        assert_eq!(nfa.run(vec!['d']), None);
+
+        let mut states: Vec<_> = nfa.iter(vec!['b']).cloned().collect();
+        states.sort();
+        assert_eq!(states, vec![0, 1, 2]);
+
+        // 'b' is on both the `Range` and the `Input` edge, so the labels are split into intervals
+        let dfa = nfa.into_dfa_by_intervals();
+        for word in &["a", "b", "bx", "by", "c", "cz", "d", "", "x"] {
+            let word: Vec<char> = word.chars().collect();
+            assert_eq!(dfa.accepts(&word), nfa.run(word.clone()).is_some());
+        }
+    }
+
+    #[test]
+    fn test_epsilon_cycle() {
+        let transitions = map!((0, Epsilon) => set!(1), (1, Epsilon) => set!(0),
+                               (1, Input('a')) => set!(2));
+        let nfa = NFA::new(0, set!(2), transitions);
+        assert_eq!(nfa.run(vec!['a']), Some(vec!['a']));
+        assert_eq!(nfa.run(vec!['b']), None);
+
+        let mut states: Vec<_> = nfa.iter(vec!['a']).cloned().collect();
+        states.sort();
+        assert_eq!(states, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_union_assign() {
+        let mut nfa = NFA::new(0, set!(1), map!((0, Input('a')) => set!(1)));
+        nfa.union_assign(&NFA::new(0, set!(2), map!((0, Input('b')) => set!(1), (1, Input('b')) => set!(2))));
+        nfa.union_assign(&NFA::new(0, set!(1), map!((0, Input('c')) => set!(1), (1, Input('c')) => set!(1))));
+
+        assert_eq!(nfa.run(vec!['a']), Some(vec!['a']));
+        assert_eq!(nfa.run(vec!['b', 'b']), Some(vec!['b', 'b']));
+        assert_eq!(nfa.run(vec!['c', 'c', 'c']), Some(vec!['c', 'c', 'c']));
+        assert_eq!(nfa.run(vec!['b']), None);
+        assert_eq!(nfa.run(vec!['a', 'b']), None);
+    }
+
+    #[test]
+    fn test_into_minimal_dfa() {
+        // Both recognize a(a|b)*
+        let nfa1 = NFA::new(0, set!(1), map!((0, Input('a')) => set!(1),
+                                             (1, Input('a')) => set!(1), (1, Input('b')) => set!(1)));
+        let nfa2 = NFA::new(0, set!(2, 3), map!((0, Input('a')) => set!(1, 2),
+                                                (1, Epsilon) => set!(3),
+                                                (2, Input('a')) => set!(2), (2, Input('b')) => set!(3),
+                                                (3, Input('a')) => set!(3), (3, Input('b')) => set!(2)));
+        let dfa1 = nfa1.into_minimal_dfa();
+        let dfa2 = nfa2.into_minimal_dfa();
+        assert!(dfa1.same_structure(&dfa2));
+        assert_eq!(dfa1.num_states(), 2);
+    }
+
+    #[test]
+    fn test_shift_and() {
+        // (a|b)*a(b|c)., with an epsilon edge, a range and a wildcard
+        let transitions = map!((0, Input('a')) => set!(0, 1),
+                               (0, Input('b')) => set!(0),
+                               (1, Range('b', 'c')) => set!(2),
+                               (2, Epsilon) => set!(3),
+                               (3, Anything) => set!(4));
+        let nfa = NFA::new(0, set!(4), transitions);
+
+        // Every word over {a, b, c} up to length 6
+        let mut inputs = vec![vec![]];
+        let mut last = vec![vec![]];
+        for _ in 0..6 {
+            last = last.iter().flat_map(|w: &Vec<char>| {
+                "abc".chars().map(move |c| { let mut w = w.clone(); w.push(c); w })
+            }).collect();
+            inputs.extend(last.iter().cloned());
+        }
+        let matcher = nfa.compile_bit_parallel().unwrap();
+        for input in inputs {
+            assert_eq!(matcher.accepts(&input), nfa.run(input.clone()).is_some());
+            assert_eq!(nfa.shift_and(&input), nfa.run(input.clone()).is_some());
+        }
+
+        // States spread over several bytes of the mask, and then too many states for a u64
+        let mut transitions = ::std::collections::HashMap::new();
+        for i in 0..70 {
+            transitions.insert((i, Input('a')), set!(i + 1));
+        }
+        let nfa = NFA::new(0, set!(20), transitions.clone().into_iter().filter(|&((i, _), _)| i < 20).collect());
+        let matcher = nfa.compile_bit_parallel().unwrap();
+        assert!(matcher.accepts(&vec!['a'; 20]));
+        assert!(!matcher.accepts(&vec!['a'; 19]));
+        assert!(!matcher.accepts(&vec!['a'; 21]));
+
+        let nfa = NFA::new(0, set!(70), transitions);
+        assert!(nfa.compile_bit_parallel().is_none());
+        assert!(nfa.shift_and(&vec!['a'; 70]));
+        assert!(!nfa.shift_and(&vec!['a'; 69]));
+
+        // The tables compiled by the first call don't outlive a change to the machine
+        let mut nfa = NFA::new(0, set!(1), map!((0, Input('a')) => set!(1)));
+        assert!(!nfa.shift_and(&['b']));
+        nfa.union_assign(&NFA::new(0, set!(1), map!((0, Input('b')) => set!(1))));
+        assert!(nfa.shift_and(&['a']));
+        assert!(nfa.shift_and(&['b']));
+    }
+
+    #[test]
+    fn test_deterministic_fragment() {
+        // State 1 is nondeterministic on 'b' and 4 has an epsilon edge; the rest is a DFA
+        let transitions = map!((0, Input('a')) => set!(1),
+                               (0, Input('b')) => set!(2),
+                               (1, Input('b')) => set!(2, 3),
+                               (2, Input('c')) => set!(3),
+                               (2, Input('d')) => set!(4),
+                               (4, Epsilon) => set!(3));
+        let nfa = NFA::new(0, set!(1, 3), transitions);
+        let dfa = nfa.deterministic_fragment();
+
+        assert_eq!(dfa.get_transitions(), &map!((0, Input('b')) => 2, (2, Input('c')) => 3));
+        assert_eq!(dfa.get_accept_states(), &set!(3));
+        assert!(dfa.accepts(&['b', 'c']));
+        assert!(nfa.run(vec!['b', 'c']).is_some());
+        assert!(!dfa.accepts(&['a']));
+
+        // Edges without targets are ignored rather than making the state nondeterministic
+        let transitions = map!((0, Input('a')) => HashSet::new(), (0, Input('b')) => set!(1));
+        let nfa = NFA::new(0, set!(1), transitions);
+        let dfa = nfa.deterministic_fragment();
+        assert_eq!(dfa.get_transitions(), &map!((0, Input('b')) => 1));
+    }
 }